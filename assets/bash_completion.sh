@@ -12,10 +12,18 @@ _qad() {
       mapfile -t COMPREPLY < <(compgen -W "app" -- "${cur}")
       return 0
     fi
-    mapfile -t COMPREPLY < <(compgen -W "app autocomplete ci debug list has-ci --version --help --file --no-tty" -- "${cur}")
+    mapfile -t COMPREPLY < <(compgen -W "app autocomplete ci debug list has-ci rerun watch graph check --version --help --file --no-tty" -- "${cur}")
     return 0
   fi
   COMPREPLY=()
+  if [ "$prev" == "--format" ]; then
+    if [ "${COMP_WORDS[1]}" == "graph" ]; then
+      mapfile -t COMPREPLY < <(compgen -W "text dot mermaid" -- "${cur}")
+    else
+      mapfile -t COMPREPLY < <(compgen -W "human ndjson" -- "${cur}")
+    fi
+    return 0
+  fi
   if [ "$COMP_CWORD" == 2 ]; then
 
     if [ "$prev" == "ci" ] || [ "$prev" == "debug" ]; then
@@ -24,7 +32,24 @@ _qad() {
       else
         jobs=()
       fi
-      mapfile -t COMPREPLY < <(compgen -W "--help ${jobs[*]}" -- "${cur}")
+      mapfile -t COMPREPLY < <(compgen -W "--help --with-deps --skip --no-cache --keep-going --strict -j --jobs --format ${jobs[*]}" -- "${cur}")
+      return 0
+    fi
+    if [ "$prev" == "watch" ]; then
+      if compgen -G "qad.y*ml*" > /dev/null; then
+        mapfile -t jobs < <(qad list 2>/dev/null)
+      else
+        jobs=()
+      fi
+      mapfile -t COMPREPLY < <(compgen -W "--help --strict --jobs ${jobs[*]}" -- "${cur}")
+      return 0
+    fi
+    if [ "$prev" == "graph" ]; then
+      mapfile -t COMPREPLY < <(compgen -W "--help --format" -- "${cur}")
+      return 0
+    fi
+    if [ "$prev" == "rerun" ]; then
+      mapfile -t COMPREPLY < <(compgen -W "--help --failed --no-cache --keep-going --strict --jobs --format" -- "${cur}")
       return 0
     fi
     if [ "$prev" == "autocomplete" ]; then
@@ -0,0 +1,131 @@
+use crate::ci::config::JobDesc;
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Hash of everything a job with `inputs` depends on:
+/// its script, its image, the env forwarded to it and the content of its input files
+pub fn fingerprint(desc: &JobDesc, env: &BTreeMap<String, String>) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut field = |name: &str, value: &[u8]| {
+        hasher.update(name.as_bytes());
+        hasher.update(value.len().to_le_bytes());
+        hasher.update(value);
+    };
+
+    field("name", desc.name.as_bytes());
+    for instruction in &desc.script {
+        field("script", instruction.as_bytes());
+    }
+    if let Some(image) = &desc.image {
+        field("image", format!("{image:?}").as_bytes());
+    }
+    for instruction in &desc.before_script.instructions {
+        field("before_script", instruction.as_bytes());
+    }
+    for instruction in &desc.after_script.instructions {
+        field("after_script", instruction.as_bytes());
+    }
+
+    for (key, value) in env {
+        field("env", key.as_bytes());
+        field("value", value.as_bytes());
+    }
+
+    for pattern in &desc.inputs {
+        let mut paths = glob::glob(pattern)
+            .map_err(|error| anyhow!("invalid inputs pattern {pattern} in {}: {error}", desc.name))?
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .collect::<Vec<PathBuf>>();
+        paths.sort();
+        for path in paths {
+            let content = fs::read(&path)
+                .map_err(|error| anyhow!("could not read {}: {error}", path.display()))?;
+            field("path", path.to_string_lossy().as_bytes());
+            field("content", &content);
+        }
+    }
+
+    Ok(hex(&hasher.finalize()))
+}
+
+/// The sha256 of `value`, in hexadecimal
+pub fn digest(value: &str) -> String {
+    hex(&Sha256::digest(value.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{byte:02x}").expect("write");
+        hex
+    })
+}
+
+/// Each job keeps the fingerprint of its last success in its own file,
+/// named after the hash of the job, as job names may hold any character
+fn entry(dir: &Path, job: &str) -> PathBuf {
+    dir.join(digest(job))
+}
+
+pub fn is_cached(dir: &Path, job: &str, fingerprint: &str) -> bool {
+    fs::read_to_string(entry(dir, job)).is_ok_and(|cached| cached == fingerprint)
+}
+
+pub fn store(dir: &Path, job: &str, fingerprint: &str) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(entry(dir, job), fingerprint)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::job::tests::temp_path;
+    use crate::strvec;
+
+    #[test]
+    pub fn fingerprint_follows_inputs() {
+        let dir = temp_path("cache");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.rs"), "fn main() {}").unwrap();
+
+        let desc = JobDesc {
+            name: "build".to_string(),
+            script: strvec!("cargo build"),
+            inputs: vec![format!("{}/*.rs", dir.display())],
+            ..Default::default()
+        };
+        let env = BTreeMap::new();
+
+        let first = fingerprint(&desc, &env).unwrap();
+        assert_eq!(first, fingerprint(&desc, &env).unwrap());
+
+        store(&dir.join("cache"), "build", &first).unwrap();
+        assert!(is_cached(&dir.join("cache"), "build", &first));
+
+        fs::write(dir.join("main.rs"), "fn main() { todo!() }").unwrap();
+        let second = fingerprint(&desc, &env).unwrap();
+        assert_ne!(first, second);
+        assert!(!is_cached(&dir.join("cache"), "build", &second));
+
+        let env = BTreeMap::from([("CI".to_string(), "true".to_string())]);
+        assert_ne!(second, fingerprint(&desc, &env).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    pub fn jobs_have_their_own_entry() {
+        let dir = temp_path("cache-entries");
+
+        store(&dir, "tests[a b]", "fingerprint").unwrap();
+        assert!(is_cached(&dir, "tests[a b]", "fingerprint"));
+        assert!(!is_cached(&dir, "tests_a_b_", "fingerprint"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
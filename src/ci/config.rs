@@ -1,17 +1,40 @@
+use crate::ci::display::OutputFormat;
 use crate::ci::job::container_configuration::{ContainerConfiguration, DockerContainer};
-use crate::ci::job::Job;
+use crate::ci::job::{HookScript, Job, Retry};
+use std::collections::HashMap;
+use std::time::Duration;
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Eq, PartialEq)]
 pub struct JobDesc {
     pub name: String,
     pub script: Vec<String>,
     pub image: Option<DockerContainer>,
     pub group: Vec<String>,
     pub skip_if: Option<String>,
+    pub timeout: Option<Duration>,
+    /// A failure of the job is reported, but does not fail the ci
+    pub allow_failure: bool,
+    /// How the failing instructions of the job run again
+    pub retry: Retry,
+    pub before_script: HookScript,
+    /// Runs even when the job fails
+    pub after_script: HookScript,
+    /// Globs of the files the job depends on, to skip it while they are unchanged
+    pub inputs: Vec<String>,
+    /// Globs of the files which make `qad watch` run the job again when they change
+    pub watch: Vec<String>,
+    /// Environment of the job, over the global `env` and the `env_file`
+    pub variables: Vec<(String, String)>,
+    /// Dotenv file holding more environment for the job
+    pub env_file: Option<String>,
 }
 
 impl From<JobDesc> for Job {
     fn from(desc: JobDesc) -> Self {
+        let timeout = desc.timeout;
+        let allow_failure = desc.allow_failure;
+        let retry = desc.retry;
+        let (before_script, after_script) = (desc.before_script, desc.after_script);
         match desc.image {
             None => Job::long(
                 desc.name,
@@ -27,19 +50,89 @@ impl From<JobDesc> for Job {
                 desc.skip_if,
             ),
         }
+        .with_timeout(timeout)
+        .allowing_failure(allow_failure)
+        .with_retry(retry)
+        .with_hooks(before_script, after_script)
+    }
+}
+
+impl JobDesc {
+    /// Explains why two definitions of the job differ when only their `env_file` does,
+    /// which is easy to miss since each one is relative to the file declaring it
+    pub fn conflict(&self, other: &Self) -> String {
+        let env_file = |job: &Self| {
+            job.env_file
+                .clone()
+                .unwrap_or_else(|| "nothing".to_string())
+        };
+        let only_env_file = Self {
+            env_file: other.env_file.clone(),
+            ..self.clone()
+        } == *other;
+        if only_env_file {
+            format!(
+                ": their env_file resolve to {} and {}",
+                env_file(self),
+                env_file(other)
+            )
+        } else {
+            String::new()
+        }
     }
 }
 
-impl PartialEq<Self> for JobDesc {
-    fn eq(&self, other: &Self) -> bool {
-        self.name == other.name
+impl Config {
+    /// The jobs a name stands for: the cells of a matrix job, or the job itself
+    pub fn expand(&self, name: &str) -> Vec<String> {
+        self.matrices
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| vec![name.to_string()])
+    }
+
+    /// Constraints naming a matrix job hold for each of its cells
+    pub fn expand_constraints(&mut self) {
+        let mut constraints = vec![];
+        let mut origins = HashMap::new();
+        for constraint in &self.constraints {
+            for blocker in self.expand(&constraint.0) {
+                for blocked in self.expand(&constraint.1) {
+                    let expanded = (blocker.clone(), blocked);
+                    if let Some(origin) = self.origins.get(constraint) {
+                        origins.insert(expanded.clone(), origin.clone());
+                    }
+                    if !constraints.contains(&expanded) {
+                        constraints.push(expanded);
+                    }
+                }
+            }
+        }
+        self.constraints = constraints;
+        self.origins = origins;
     }
 }
 
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Default, Clone)]
 pub struct CliOption {
-    pub job: Option<String>,
+    /// Jobs, or groups prefixed with `group:`, to run instead of the whole ci.
+    /// The constraints between them are kept
+    pub only: Vec<String>,
+    /// Also runs the jobs the selected ones depend on
+    pub with_deps: bool,
+    /// Runs the jobs even if their inputs are unchanged since their last success
+    pub no_cache: bool,
+    /// Jobs, or groups prefixed with `group:`, left out of the run.
+    /// Names or globs, with the syntax of the `inputs` and `watch` lists
+    pub skip: Vec<String>,
+    /// A failure only cancels the jobs which need the failed one, not the next groups
+    pub keep_going: bool,
+    /// The jobs allowed to fail still fail the ci
+    pub strict: bool,
     pub no_tty: bool,
+    pub jobs: Option<usize>,
+    pub format: OutputFormat,
 }
 
 #[derive(Default, Clone)]
@@ -47,4 +140,18 @@ pub struct Config {
     pub jobs: Vec<JobDesc>,
     pub groups: Vec<String>,
     pub constraints: Vec<(String, String)>,
+    /// The extra file declaring each constraint which does not come from the main config
+    pub origins: HashMap<(String, String), String>,
+    /// The cells of each matrix job, which replace it in the jobs
+    pub matrices: HashMap<String, Vec<String>>,
+    /// Env keys whose values are masked in every output
+    pub secrets: Vec<String>,
+    pub concurrency: Option<usize>,
+    pub timeout: Option<Duration>,
+    /// Default of `--keep-going`
+    pub keep_going: bool,
+    /// Runs before the jobs
+    pub setup: HookScript,
+    /// Runs after the jobs, even when the ci fails or is interrupted
+    pub teardown: HookScript,
 }
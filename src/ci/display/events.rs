@@ -0,0 +1,268 @@
+use crate::ci::job::inspection::JobProgressTracker;
+use crate::ci::job::ports::UserFacade;
+use crate::ci::job::{Output, Progress};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Writes each progress of the ci as a json object on its own line (ndjson)
+/// so that other tools can follow a run without parsing the terminal output
+pub struct Display<'a> {
+    write: &'a mut dyn Write,
+    written: HashMap<String, usize>,
+    /// How many lines of each job were written
+    lines_written: HashMap<String, usize>,
+}
+
+impl UserFacade for Display<'_> {
+    fn set_up(&mut self, tracker: &JobProgressTracker) {
+        self.write_new_events(tracker);
+    }
+
+    fn run(&mut self, tracker: &JobProgressTracker, _: usize) {
+        self.write_new_events(tracker);
+    }
+
+    fn tear_down(&mut self, tracker: &JobProgressTracker) {
+        self.write_new_events(tracker);
+        let mut event = Map::new();
+        event.insert("timestamp".to_string(), json!(timestamp(SystemTime::now())));
+        event.insert("event".to_string(), json!("ci_finished"));
+        event.insert("success".to_string(), json!(!tracker.has_failed));
+        event.insert(
+            "allowed_failures".to_string(),
+            json!(tracker.has_allowed_failures),
+        );
+        event.insert("interrupted".to_string(), json!(tracker.interrupted));
+        self.write_event(&Value::Object(event));
+    }
+
+    fn display_error(&self, error: String) {
+        eprintln!("{error}");
+    }
+}
+
+impl<'a> Display<'a> {
+    pub fn new(write: &'a mut dyn Write) -> Self {
+        Self {
+            write,
+            written: HashMap::new(),
+            lines_written: HashMap::new(),
+        }
+    }
+
+    fn write_new_events(&mut self, tracker: &JobProgressTracker) {
+        // the setup and the teardown belong to no job
+        let collectors = std::iter::once((None, &tracker.hooks)).chain(
+            tracker
+                .states
+                .iter()
+                .map(|(job_name, collector)| (Some(job_name.as_str()), collector)),
+        );
+        let mut events: Vec<(SystemTime, Value)> = vec![];
+        for (job_name, collector) in collectors {
+            let written = self
+                .written
+                .entry(job_name.unwrap_or_default().to_string())
+                .or_default();
+            events.extend(collector.timed().skip(*written).map(|(time, progress)| {
+                (
+                    time,
+                    event(job_name, time, progress, collector.allow_failure),
+                )
+            }));
+            *written = collector.progresses.len();
+
+            let lines_written = self
+                .lines_written
+                .entry(job_name.unwrap_or_default().to_string())
+                .or_default();
+            events.extend(
+                collector
+                    .lines
+                    .iter()
+                    .skip(*lines_written)
+                    .map(|(time, line)| {
+                        let line = Progress::Line(line.clone());
+                        (
+                            *time,
+                            event(job_name, *time, &line, collector.allow_failure),
+                        )
+                    }),
+            );
+            *lines_written = collector.lines.len();
+        }
+        // the teardown comes after the last progresses of the jobs
+        events.sort_by_key(|(time, _)| *time);
+        for (_, event) in events {
+            self.write_event(&event);
+        }
+        self.write.flush().expect("Can't flush");
+    }
+
+    fn write_event(&mut self, event: &Value) {
+        serde_json::to_writer(&mut *self.write, event).expect("Can't write");
+        self.write.write_all(b"\n").expect("Can't write");
+    }
+}
+
+/// Milliseconds since the unix epoch
+fn timestamp(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default()
+}
+
+fn event(
+    job_name: Option<&str>,
+    time: SystemTime,
+    progress: &Progress,
+    allow_failure: bool,
+) -> Value {
+    let mut event = Map::new();
+    event.insert("timestamp".to_string(), json!(timestamp(time)));
+    if let Some(job_name) = job_name {
+        event.insert("job".to_string(), json!(job_name));
+    }
+    let mut set = |key: &str, value: Value| event.insert(key.to_string(), value);
+
+    match progress {
+        Progress::Available => set("event", json!("available")),
+        Progress::Queued => set("event", json!("queued")),
+        Progress::Blocked(blocked_by) => {
+            set("blocked_by", json!(blocked_by));
+            set("event", json!("blocked"))
+        }
+        Progress::Cancelled => set("event", json!("cancelled")),
+        Progress::Started(instruction) => {
+            set("instruction", json!(instruction));
+            set("event", json!("started"))
+        }
+        Progress::Line(line) => {
+            set("line", json!(line));
+            set("event", json!("line"))
+        }
+        Progress::Partial(instruction, output) => {
+            set("instruction", json!(instruction));
+            let (status, exit_code, stdout, stderr) = output_fields(output);
+            set("status", json!(status));
+            set("exit_code", json!(exit_code));
+            set("stdout", json!(stdout));
+            set("stderr", json!(stderr));
+            set("event", json!("instruction_finished"))
+        }
+        Progress::Hook(hook, instruction, output) => {
+            set("hook", json!(hook.to_string()));
+            set("instruction", json!(instruction));
+            let (status, exit_code, stdout, stderr) = output_fields(output);
+            set("status", json!(status));
+            set("exit_code", json!(exit_code));
+            set("stdout", json!(stdout));
+            set("stderr", json!(stderr));
+            set("event", json!("hook_finished"))
+        }
+        Progress::ContainerStart(output) => {
+            let (status, exit_code, stdout, stderr) = output_fields(output);
+            set("status", json!(status));
+            set("exit_code", json!(exit_code));
+            set("stdout", json!(stdout));
+            set("stderr", json!(stderr));
+            set("event", json!("container_started"))
+        }
+        Progress::Retrying(attempt, attempts) => {
+            set("attempt", json!(attempt));
+            set("attempts", json!(attempts));
+            set("event", json!("retrying"))
+        }
+        Progress::Skipped(reason) => {
+            set("reason", json!(reason));
+            set("event", json!("skipped"))
+        }
+        Progress::Terminated(success) => {
+            set("success", json!(success));
+            set("allow_failure", json!(allow_failure));
+            set("event", json!("terminated"))
+        }
+    };
+    Value::Object(event)
+}
+
+/// The status of an output, its exit code if the instruction exited, then its stdout and stderr
+fn output_fields(output: &Output) -> (&str, Option<i32>, &str, &str) {
+    let (stdout, stderr) = output.streams();
+    let (status, exit_code) = match output {
+        Output::Success(_, _) => ("success", Some(0)),
+        Output::JobError(_, _, code) => ("job_error", Some(*code)),
+        Output::TimedOut(_, _) => ("timed_out", None),
+        Output::ProcessError(_) => ("process_error", None),
+    };
+    (status, exit_code, stdout, stderr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::job::inspection::JobProgress;
+
+    #[test]
+    pub fn writes_each_progress_once() {
+        let mut tracker = JobProgressTracker::new();
+        tracker.record(JobProgress::new("build", Progress::Available));
+        let mut buffer: Vec<u8> = vec![];
+        let mut display = Display::new(&mut buffer);
+
+        display.set_up(&tracker);
+        tracker.record(JobProgress::new(
+            "build",
+            Progress::ContainerStart(Output::Success("abc123".to_string(), String::new())),
+        ));
+        tracker.record(JobProgress::new(
+            "build",
+            Progress::Started("make".to_string()),
+        ));
+        tracker.record(JobProgress::new("build", Progress::Line("out".to_string())));
+        tracker.record(JobProgress::new(
+            "build",
+            Progress::Partial(
+                "make".to_string(),
+                Output::JobError("out".to_string(), "err".to_string(), 2),
+            ),
+        ));
+        tracker.record(JobProgress::new("build", Progress::Terminated(false)));
+        display.run(&tracker, 0);
+        display.run(&tracker, 0);
+        display.tear_down(&tracker);
+
+        let events: Vec<Value> = String::from_utf8(buffer)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|event| event["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                "available",
+                "container_started",
+                "started",
+                "line",
+                "instruction_finished",
+                "terminated",
+                "ci_finished"
+            ],
+            kinds
+        );
+        assert_eq!("success", events[1]["status"]);
+        assert_eq!("abc123", events[1]["stdout"]);
+        assert_eq!("out", events[3]["line"]);
+        assert_eq!("build", events[4]["job"]);
+        assert_eq!("job_error", events[4]["status"]);
+        assert_eq!(2, events[4]["exit_code"]);
+        assert_eq!("err", events[4]["stderr"]);
+        assert_eq!(false, events[6]["success"]);
+    }
+}
@@ -1,9 +1,9 @@
 use crate::ci::clean::try_cleanup;
 use crate::ci::display::ansi_control_sequence::{ResetChar, UnderlineChar};
-use crate::ci::display::CiDisplayConfig;
-use crate::ci::job::inspection::JobProgressTracker;
+use crate::ci::display::{format_duration, CiDisplayConfig};
+use crate::ci::job::inspection::{JobProgressTracker, ProgressCollector};
 use crate::ci::job::ports::FinalCiDisplay;
-use crate::ci::job::{Output, Progress};
+use crate::ci::job::{Hook, Output, Progress};
 use std::fmt::Write;
 use std::time::SystemTime;
 
@@ -19,23 +19,51 @@ impl<'a> FullFinalDisplay<'a> {
 
 impl FinalCiDisplay for FullFinalDisplay<'_> {
     fn finish(&mut self, tracker: &JobProgressTracker) {
+        self.print_hooks(tracker, Hook::Setup);
         for (job_name, progress_collector) in &tracker.states {
             let mut string = String::new();
             let mut icon = String::new();
-            for progress in &progress_collector.progresses {
+            let mut started = None;
+            for (time, progress) in progress_collector.timed() {
+                let took = started
+                    .and_then(|start| time.duration_since(start).ok())
+                    .map(format_duration)
+                    .unwrap_or_default();
                 match progress {
+                    Progress::Started(_) => started = Some(time),
                     Progress::Cancelled => {
                         icon.clone_from(&self.config.cancelled);
                     }
-                    Progress::Skipped => string.push_str("  job was skipped\n"),
+                    Progress::Skipped(reason) => {
+                        writeln!(string, "  job was skipped: {reason}").expect("write");
+                    }
+                    Progress::Hook(hook, instruction, output) => {
+                        self.write_step(&mut string, &format!("{hook}: {instruction}"), output);
+                    }
+                    Progress::ContainerStart(output) => {
+                        self.write_step(&mut string, "start the container", output);
+                    }
+                    Progress::Retrying(attempt, attempts) => {
+                        writeln!(string, "  attempt {attempt}/{attempts}:").expect("write");
+                    }
                     Progress::Partial(instruction, job_output) => match job_output {
-                        Output::Success(stdout, stderr) | Output::JobError(stdout, stderr) => {
-                            let symbol = if job_output.succeeded() {
-                                &self.config.ok
-                            } else {
-                                &self.config.ko
-                            };
-                            writeln!(string, "  {symbol} {instruction}").expect("write");
+                        Output::Success(stdout, stderr)
+                        | Output::JobError(stdout, stderr, _)
+                        | Output::TimedOut(stdout, stderr) => {
+                            match job_output {
+                                Output::Success(_, _) => {
+                                    writeln!(string, "  {} {instruction} ({took})", self.config.ok)
+                                }
+                                Output::TimedOut(_, _) => writeln!(
+                                    string,
+                                    "  {} {instruction} (timed out after {took})",
+                                    self.config.timed_out
+                                ),
+                                _ => {
+                                    writeln!(string, "  {} {instruction} ({took})", self.config.ko)
+                                }
+                            }
+                            .expect("write");
                             string.push_str(&try_cleanup(&format!(
                                 "  {}\n  {}",
                                 try_cleanup(stdout).replace('\n', "\n    "),
@@ -51,20 +79,20 @@ impl FinalCiDisplay for FullFinalDisplay<'_> {
                         .expect("write"),
                     },
                     Progress::Terminated(bool) => {
-                        let emoji: &str = if *bool {
-                            icon.clone_from(&self.config.ok);
-                            &self.config.ok
-                        } else {
-                            icon.clone_from(&self.config.ko);
-                            &self.config.ko
-                        };
+                        let emoji = self.terminated_icon(*bool, progress_collector);
+                        emoji.clone_into(&mut icon);
+                        let duration = progress_collector
+                            .duration()
+                            .map(|duration| format!(" in {}", format_duration(duration)))
+                            .unwrap_or_default();
                         writeln!(
                             string,
-                            "  {} {}all tasks done for job {}{}",
+                            "  {} {}all tasks done for job {}{}{}",
                             emoji,
                             UnderlineChar(),
                             job_name,
-                            ResetChar()
+                            ResetChar(),
+                            duration
                         )
                         .expect("write");
                     }
@@ -77,11 +105,9 @@ impl FinalCiDisplay for FullFinalDisplay<'_> {
             }
         }
 
-        let status = if tracker.has_failed {
-            (&self.config.ko, "failed")
-        } else {
-            (&self.config.ok, "succeeded")
-        };
+        self.print_hooks(tracker, Hook::Teardown);
+
+        let status = self.status(tracker);
 
         println!(
             "\n{} ci {} in {:.2} seconds",
@@ -93,6 +119,62 @@ impl FinalCiDisplay for FullFinalDisplay<'_> {
 }
 
 impl FullFinalDisplay<'_> {
+    /// The icon and the word telling how the ci went
+    fn status(&self, tracker: &JobProgressTracker) -> (&str, &str) {
+        if tracker.interrupted {
+            (&self.config.cancelled, "was interrupted")
+        } else if tracker.has_failed {
+            (&self.config.ko, "failed")
+        } else if tracker.has_allowed_failures {
+            (&self.config.warn, "succeeded with allowed failures")
+        } else {
+            (&self.config.ok, "succeeded")
+        }
+    }
+
+    /// The line of a step around the instructions of a job, followed by its output
+    fn write_step(&self, string: &mut String, step: &str, output: &Output) {
+        let icon = match output {
+            Output::Success(_, _) => &self.config.ok,
+            Output::TimedOut(_, _) => &self.config.timed_out,
+            Output::JobError(_, _, _) | Output::ProcessError(_) => &self.config.ko,
+        };
+        writeln!(string, "  {icon} {step}").expect("write");
+        let (stdout, stderr) = output.streams();
+        let text = try_cleanup(&format!("{stdout}{stderr}"));
+        if !text.trim().is_empty() {
+            writeln!(string, "    {}", text.trim_end().replace('\n', "\n    ")).expect("write");
+        }
+    }
+
+    /// The setup or the teardown of the ci, if it ran
+    fn print_hooks(&self, tracker: &JobProgressTracker, hook: Hook) {
+        let mut string = String::new();
+        for progress in &tracker.hooks.progresses {
+            if let Progress::Hook(ran, instruction, output) = progress {
+                if *ran == hook {
+                    self.write_step(&mut string, &format!("{hook}: {instruction}"), output);
+                }
+            }
+        }
+        if !string.is_empty() {
+            println!("{hook}:");
+            print!("{string}");
+        }
+    }
+
+    fn terminated_icon(&self, success: bool, collector: &ProgressCollector) -> &str {
+        if success {
+            &self.config.ok
+        } else if collector.allow_failure {
+            &self.config.warn
+        } else if collector.timed_out() {
+            &self.config.timed_out
+        } else {
+            &self.config.ko
+        }
+    }
+
     /// I'd rather have no info over a system error when reporting time
     fn elapsed(tracker: &JobProgressTracker) -> Option<f64> {
         let time = tracker.end_time.or_else(|| Some(SystemTime::now()))?;
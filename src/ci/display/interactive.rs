@@ -1,6 +1,7 @@
+use crate::ci::clean::try_cleanup;
+use crate::ci::display::{format_duration, CiDisplayConfig};
 use crate::ci::job::inspection::JobProgressTracker;
 use crate::ci::job::ports::FinalCiDisplay;
-use crate::ci::{clean::try_cleanup, display::CiDisplayConfig};
 use ansi_to_tui::IntoText;
 use anyhow::{anyhow, Result};
 
@@ -87,13 +88,16 @@ fn finish_error(tracker: &JobProgressTracker) -> Result<()> {
 pub enum JobResult {
     Success,
     Failure,
+    /// Failed, but was allowed to
+    Warning,
+    TimedOut,
     Unknown,
 }
 
 impl From<&Progress> for JobResult {
     fn from(progress: &Progress) -> Self {
         match progress {
-            Progress::Skipped | Progress::Terminated(true) => Self::Success,
+            Progress::Skipped(_) | Progress::Terminated(true) => Self::Success,
             Progress::Cancelled | Progress::Terminated(false) => Self::Failure,
             _ => Self::Unknown,
         }
@@ -101,9 +105,10 @@ impl From<&Progress> for JobResult {
 }
 
 struct App<'a> {
-    items: StatefulList<(JobResult, String)>,
+    items: StatefulList<(JobResult, String, Option<Duration>)>,
     tracker: &'a JobProgressTracker,
     right_panel: Option<StatefulText>,
+    sorted_by_duration: bool,
 }
 
 impl App<'_> {
@@ -132,6 +137,22 @@ impl App<'_> {
         self.right_panel = None;
     }
 
+    /// Switches between the slowest jobs first and the configuration order
+    pub fn toggle_sort(&mut self) {
+        self.sorted_by_duration = !self.sorted_by_duration;
+        if self.sorted_by_duration {
+            self.items
+                .items
+                .sort_by_key(|(_, _, duration)| std::cmp::Reverse(*duration));
+        } else {
+            let tracker = self.tracker;
+            self.items
+                .items
+                .sort_by_key(|(_, name, _)| tracker.states.get_index_of(name));
+        }
+        self.items.state.select(Some(0));
+    }
+
     fn selected_text(&self) -> String {
         let selected = self
             .items
@@ -140,24 +161,32 @@ impl App<'_> {
             .or(Some(0))
             .expect("has fallback");
 
-        let collector = match self.tracker.states.iter().nth(selected) {
-            Some(item) => item.1,
-            None => {
-                return String::new();
-            }
+        let Some(collector) = self
+            .items
+            .items
+            .get(selected)
+            .and_then(|(_, name, _)| self.tracker.states.get(name))
+        else {
+            return String::new();
         };
 
         let progress_items = collector
             .progresses
             .iter()
             .filter_map(|progres| match progres {
-                Progress::Partial(_, Output::Success(out, err) | Output::JobError(out, err)) => {
+                Progress::Partial(_, Output::Success(out, err) | Output::JobError(out, err, _)) => {
                     Some(format!("{out}\n{err}"))
                 }
-                Progress::Partial(_, Output::ProcessError(err)) => Some(err.to_string()),
-                Progress::Skipped => Some("skipped".to_string()),
+                Progress::Partial(_, Output::TimedOut(out, err)) => {
+                    Some(format!("{out}\n{err}\ntimed out"))
+                }
+                Progress::Partial(_, Output::ProcessError(err)) => Some(err.clone()),
+                Progress::Skipped(reason) => Some(format!("skipped: {reason}")),
                 Progress::Cancelled => Some("cancelled".to_string()),
                 Progress::Terminated(true) => Some("success".to_string()),
+                Progress::Terminated(false) if collector.allow_failure => {
+                    Some("failure, allowed".to_string())
+                }
                 Progress::Terminated(false) => Some("failure".to_string()),
                 _ => None,
             })
@@ -170,12 +199,20 @@ impl<'a> From<&'a JobProgressTracker> for App<'a> {
     fn from(tracker: &'a JobProgressTracker) -> Self {
         let mut items = vec![];
         for (name, state) in &tracker.states {
-            items.push((JobResult::from(state.last()), name.to_string()));
+            let result = if state.failure_allowed() {
+                JobResult::Warning
+            } else if state.timed_out() {
+                JobResult::TimedOut
+            } else {
+                JobResult::from(state.last())
+            };
+            items.push((result, name.clone(), state.duration()));
         }
         Self {
             items: StatefulList::with_items(items),
             tracker,
             right_panel: None,
+            sorted_by_duration: false,
         }
     }
 }
@@ -204,6 +241,7 @@ fn run_app<B: Backend>(
                     KeyCode::Left | KeyCode::Backspace => app.unselect(),
                     KeyCode::Down => app.next(),
                     KeyCode::Up => app.previous(),
+                    KeyCode::Char('s') => app.toggle_sort(),
                     _ => {}
                 }
             }
@@ -246,9 +284,15 @@ fn ui(f: &mut Frame, app: &mut App) {
         .items
         .items
         .iter()
-        .map(|(result, item)| {
-            ListItem::new(Span::from(item.to_string())).style(Style::default().fg(match result {
+        .map(|(result, item, duration)| {
+            let label = duration.map_or_else(
+                || item.clone(),
+                |duration| format!("{item} {}", format_duration(duration)),
+            );
+            ListItem::new(Span::from(label)).style(Style::default().fg(match result {
                 JobResult::Success => Color::Green,
+                JobResult::TimedOut => Color::Yellow,
+                JobResult::Warning => Color::LightYellow,
                 JobResult::Failure | JobResult::Unknown => Color::Red,
             }))
         })
@@ -256,7 +300,15 @@ fn ui(f: &mut Frame, app: &mut App) {
 
     // Create a List from all list items and highlight the currently selected one
     let items = List::new(items)
-        .block(Block::default().borders(Borders::ALL).title("jobs"))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(if app.sorted_by_duration {
+                    "jobs (slowest first)"
+                } else {
+                    "jobs (s: sort by duration)"
+                }),
+        )
         .highlight_style(Style::default().add_modifier(Modifier::BOLD))
         .highlight_symbol(app.right_panel.as_ref().map_or(">  ", |_| ">> "));
 
@@ -0,0 +1,267 @@
+use crate::ci::display::CiDisplayConfig;
+use crate::ci::job::inspection::{JobProgressTracker, ProgressCollector};
+use crate::ci::job::ports::FinalCiDisplay;
+use crate::ci::job::{Output, Progress};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Writes the result of the ci as a junit xml report,
+/// with a testsuite per group and a testcase per job
+pub struct JunitFinalDisplay<'a> {
+    config: &'a CiDisplayConfig,
+    groups: HashMap<String, String>,
+}
+
+impl<'a> JunitFinalDisplay<'a> {
+    /// `groups` maps job names to their group
+    pub const fn new(config: &'a CiDisplayConfig, groups: HashMap<String, String>) -> Self {
+        Self { config, groups }
+    }
+
+    fn render(&self, tracker: &JobProgressTracker) -> String {
+        let mut suites = IndexMap::<&str, Vec<(&str, &ProgressCollector)>>::new();
+        for (job_name, collector) in &tracker.states {
+            let suite = self
+                .groups
+                .get(job_name)
+                .map_or(crate::PACKAGE_NAME, String::as_str);
+            suites.entry(suite).or_default().push((job_name, collector));
+        }
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+        for (suite, jobs) in suites {
+            let cases: Vec<TestCase> = jobs
+                .iter()
+                .map(|(name, collector)| TestCase::from(name, collector))
+                .collect();
+            let count = |kind: fn(&Outcome) -> bool| {
+                cases.iter().filter(|case| kind(&case.outcome)).count()
+            };
+            writeln!(
+                xml,
+                r#"  <testsuite name="{}" tests="{}" failures="{}" errors="{}" skipped="{}">"#,
+                escape(suite),
+                cases.len(),
+                count(|outcome| matches!(outcome, Outcome::Failure(_))),
+                count(|outcome| matches!(outcome, Outcome::Error(_))),
+                count(|outcome| matches!(outcome, Outcome::Skipped(_))),
+            )
+            .expect("write");
+            for case in cases {
+                case.write(&mut xml, suite);
+            }
+            xml.push_str("  </testsuite>\n");
+        }
+        xml.push_str("</testsuites>\n");
+        xml
+    }
+}
+
+impl FinalCiDisplay for JunitFinalDisplay<'_> {
+    fn finish(&mut self, tracker: &JobProgressTracker) {
+        let path = &self.config.junit_file;
+        match std::fs::write(path, self.render(tracker)) {
+            Ok(()) => println!("junit report written to {path}"),
+            Err(e) => eprintln!("could not write junit report to {path}: {e}"),
+        }
+    }
+}
+
+enum Outcome {
+    Success,
+    Failure(String),
+    Error(String),
+    Skipped(String),
+}
+
+struct TestCase<'a> {
+    name: &'a str,
+    outcome: Outcome,
+    stdout: String,
+    stderr: String,
+}
+
+impl<'a> TestCase<'a> {
+    fn from(name: &'a str, collector: &ProgressCollector) -> Self {
+        let mut outcome = Outcome::Success;
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        let mut failed_hook = None;
+        for progress in &collector.progresses {
+            match progress {
+                Progress::Skipped(reason) => {
+                    outcome = Outcome::Skipped(format!("job was skipped: {reason}"));
+                }
+                Progress::Cancelled => outcome = Outcome::Skipped("job was cancelled".to_string()),
+                Progress::Retrying(attempt, attempts) => {
+                    writeln!(stdout, "attempt {attempt}/{attempts}").expect("write");
+                    outcome = Outcome::Success;
+                }
+                Progress::Hook(hook, instruction, output) => {
+                    writeln!(stdout, "$ [{hook}] {instruction}").expect("write");
+                    push_streams(&mut stdout, &mut stderr, output);
+                    if !output.succeeded() {
+                        failed_hook = Some(format!("{hook} {instruction} failed"));
+                    }
+                }
+                Progress::ContainerStart(output) => {
+                    writeln!(stdout, "$ start the container").expect("write");
+                    push_streams(&mut stdout, &mut stderr, output);
+                    if !output.succeeded() {
+                        failed_hook = Some("the container failed to start".to_string());
+                    }
+                }
+                Progress::Partial(instruction, output) => {
+                    writeln!(stdout, "$ {instruction}").expect("write");
+                    push_streams(&mut stdout, &mut stderr, output);
+                    match output {
+                        Output::Success(_, _) => {}
+                        Output::JobError(_, _, _) => {
+                            outcome = Outcome::Failure(format!("{instruction} failed"));
+                        }
+                        Output::TimedOut(_, _) => {
+                            outcome = Outcome::Failure(format!("{instruction} timed out"));
+                        }
+                        Output::ProcessError(_) => {
+                            outcome = Outcome::Error(format!("{instruction} could not run"));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        // the instructions passed, a fatal hook failed the job
+        if matches!(outcome, Outcome::Success) && collector.terminated() == Some(false) {
+            outcome = Outcome::Failure(failed_hook.unwrap_or_else(|| "job failed".to_string()));
+        }
+        if collector.allow_failure {
+            if let Outcome::Failure(message) | Outcome::Error(message) = outcome {
+                outcome = Outcome::Skipped(format!("{message}, which the job is allowed to"));
+            }
+        }
+        Self {
+            name,
+            outcome,
+            stdout,
+            stderr,
+        }
+    }
+
+    fn write(&self, xml: &mut String, suite: &str) {
+        writeln!(
+            xml,
+            r#"    <testcase name="{}" classname="{}">"#,
+            escape(self.name),
+            escape(suite)
+        )
+        .expect("write");
+        match &self.outcome {
+            Outcome::Success => {}
+            Outcome::Failure(message) => {
+                writeln!(xml, r#"      <failure message="{}"/>"#, escape(message)).expect("write");
+            }
+            Outcome::Error(message) => {
+                writeln!(xml, r#"      <error message="{}"/>"#, escape(message)).expect("write");
+            }
+            Outcome::Skipped(message) => {
+                writeln!(xml, r#"      <skipped message="{}"/>"#, escape(message)).expect("write");
+            }
+        }
+        if !self.stdout.is_empty() {
+            writeln!(
+                xml,
+                "      <system-out>{}</system-out>",
+                escape(&self.stdout)
+            )
+            .expect("write");
+        }
+        if !self.stderr.is_empty() {
+            writeln!(
+                xml,
+                "      <system-err>{}</system-err>",
+                escape(&self.stderr)
+            )
+            .expect("write");
+        }
+        xml.push_str("    </testcase>\n");
+    }
+}
+
+/// Appends the streams of an output to the ones of the test case
+fn push_streams(stdout: &mut String, stderr: &mut String, output: &Output) {
+    let (out, err) = output.streams();
+    stdout.push_str(out);
+    stderr.push_str(err);
+}
+
+/// Escapes xml special characters, and drops terminal escape sequences
+/// and control characters which are not allowed in xml documents
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            // a terminal escape sequence, such as a color, ends with a letter
+            '\x1b' if chars.peek() == Some(&'[') => {
+                chars.next();
+                while chars
+                    .next_if(|char| char.is_ascii_digit() || ";?".contains(*char))
+                    .is_some()
+                {}
+                chars.next_if(char::is_ascii_alphabetic);
+            }
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(char),
+            char if char.is_control() => {}
+            char => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::job::inspection::JobProgress;
+
+    #[test]
+    pub fn report() {
+        let mut tracker = JobProgressTracker::new();
+        tracker.record(JobProgress::new(
+            "fmt",
+            Progress::Partial(
+                "cargo fmt".to_string(),
+                Output::Success("\x1b[32mok\x1b[0m".to_string(), String::new()),
+            ),
+        ));
+        tracker.record(JobProgress::new("fmt", Progress::Terminated(true)));
+        tracker.record(JobProgress::new(
+            "tests",
+            Progress::Partial(
+                "cargo test".to_string(),
+                Output::JobError(String::new(), "a < b".to_string(), 1),
+            ),
+        ));
+        tracker.record(JobProgress::new("tests", Progress::Terminated(false)));
+        tracker.record(JobProgress::cancel("deploy".to_string()));
+
+        let config = CiDisplayConfig::default();
+        let groups = HashMap::from([("tests".to_string(), "test".to_string())]);
+        let xml = JunitFinalDisplay::new(&config, groups).render(&tracker);
+
+        assert!(
+            xml.contains(r#"<testsuite name="qad" tests="2" failures="0" errors="0" skipped="1">"#)
+        );
+        assert!(xml.contains("<system-out>$ cargo fmt\nok</system-out>"));
+        assert!(xml
+            .contains(r#"<testsuite name="test" tests="1" failures="1" errors="0" skipped="0">"#));
+        assert!(xml.contains(r#"<failure message="cargo test failed"/>"#));
+        assert!(xml.contains("<system-err>a &lt; b</system-err>"));
+        assert!(xml.contains(r#"<skipped message="job was cancelled"/>"#));
+    }
+}
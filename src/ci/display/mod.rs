@@ -1,6 +1,8 @@
 mod ansi_control_sequence;
+pub mod events;
 pub mod exhaustive;
 pub mod interactive;
+pub mod junit;
 pub mod sequence;
 pub mod silent;
 mod spinner;
@@ -8,30 +10,46 @@ pub mod summary;
 mod term_wrapper;
 mod tui;
 
-#[derive(Clone, Copy, Debug)]
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, Default)]
 pub enum Running {
     Silent,
+    #[default]
     Sequence,
     Summary,
 }
 
-#[derive(Clone, Copy, Debug)]
-pub enum FinalDisplayMode {
-    Full,
-    Interactive,
-    Silent,
+/// How `qad ci` reports to its caller
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// Running and final displays, as configured
+    #[default]
+    Human,
+    /// One json event per line on stdout, for other programs
+    Ndjson,
 }
 
-impl Default for Running {
-    fn default() -> Self {
-        Self::Sequence
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "ndjson" => Ok(Self::Ndjson),
+            _ => Err(format!("unknown format {s} (expected human or ndjson)")),
+        }
     }
 }
 
-impl Default for FinalDisplayMode {
-    fn default() -> Self {
-        Self::Full
-    }
+#[derive(Clone, Copy, Debug, Default)]
+pub enum FinalDisplayMode {
+    #[default]
+    Full,
+    Interactive,
+    Junit,
+    Silent,
 }
 
 #[derive(Clone)]
@@ -40,7 +58,11 @@ pub struct CiDisplayConfig {
     pub final_display: FinalDisplayMode,
     pub ok: String,
     pub ko: String,
+    /// Jobs which failed, but were allowed to
+    pub warn: String,
     pub cancelled: String,
+    pub timed_out: String,
+    pub junit_file: String,
     pub spinner: (Vec<String>, usize),
 }
 
@@ -51,7 +73,10 @@ impl Default for CiDisplayConfig {
             final_display: FinalDisplayMode::default(),
             ok: String::from("✔"),
             ko: String::from("✕"),
+            warn: String::from("⚠"),
             cancelled: String::from("✕"),
+            timed_out: String::from("⧗"),
+            junit_file: String::from("qad-junit.xml"),
             spinner: (
                 vec![
                     String::from(".  "),
@@ -65,3 +90,30 @@ impl Default for CiDisplayConfig {
         }
     }
 }
+
+/// Short human readable duration, like 850ms, 4.2s or 3m07s
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60)
+    } else if seconds >= 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else if seconds >= 1 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else {
+        format!("{}ms", duration.as_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn durations() {
+        assert_eq!("850ms", format_duration(Duration::from_millis(850)));
+        assert_eq!("4.2s", format_duration(Duration::from_millis(4200)));
+        assert_eq!("3m07s", format_duration(Duration::from_secs(187)));
+        assert_eq!("1h02m", format_duration(Duration::from_secs(3725)));
+    }
+}
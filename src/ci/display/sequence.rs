@@ -24,6 +24,14 @@ impl UserFacade for Display<'_> {
             let result = self.display(job_name, progress_collector);
             self.term.write(&result);
             self.term.newline();
+            if matches!(progress_collector.last(), Progress::Started(_)) {
+                for line in progress_collector.tail() {
+                    let line = line.rsplit('\r').next().unwrap_or_default();
+                    self.term
+                        .write(&format!("{:2$}  {}", "", line, self.max_job_name_len));
+                    self.term.newline();
+                }
+            }
         }
         self.spin.tick(elapsed);
     }
@@ -48,6 +56,15 @@ impl<'a> Display<'a> {
             Progress::Available => {
                 str.push_str("not started yet");
             }
+            Progress::Queued => {
+                str.push_str("queued, waiting for a free slot");
+            }
+            Progress::Terminated(false) if collector.allow_failure => {
+                write!(str, " {} failed (allowed)", self.config.warn).expect("Can't write");
+            }
+            Progress::Terminated(false) if collector.timed_out() => {
+                write!(str, " {} timed out", self.config.timed_out).expect("Can't write");
+            }
             Progress::Terminated(state) => {
                 write!(
                     str,
@@ -60,11 +77,21 @@ impl<'a> Display<'a> {
                 )
                 .expect("Can't write");
             }
-            Progress::Partial(_, _) => {
+            Progress::Partial(_, _) | Progress::Line(_) => {
                 write!(str, " {}", self.spin).expect("Can't write");
             }
-            Progress::Skipped => {
-                write!(str, " {} job was skipped", self.config.ok).expect("Can't write");
+            Progress::Hook(hook, _, _) => {
+                write!(str, " {hook} {}", self.spin).expect("Can't write");
+            }
+            Progress::ContainerStart(_) => {
+                write!(str, " container {}", self.spin).expect("Can't write");
+            }
+            Progress::Retrying(attempt, attempts) => {
+                write!(str, " {} retrying, attempt {attempt}/{attempts}", self.spin)
+                    .expect("Can't write");
+            }
+            Progress::Skipped(reason) => {
+                write!(str, " {} job was skipped ({reason})", self.config.ok).expect("Can't write");
             }
             Progress::Blocked(blocked_by) => {
                 write!(str, " blocked by ").expect("Can't write");
@@ -1,8 +1,9 @@
 use crate::ci::display::spinner::Spinner;
 use crate::ci::display::term_wrapper::TermWrapper;
-use crate::ci::display::CiDisplayConfig;
+use crate::ci::display::{format_duration, CiDisplayConfig};
 use crate::ci::job::inspection::{InstructionState, JobProgressTracker, ProgressCollector};
 use crate::ci::job::ports::UserFacade;
+use crate::ci::job::Progress;
 use std::io::Write;
 
 pub struct Display<'a> {
@@ -43,9 +44,18 @@ impl<'a> Display<'a> {
         if let Some(result) = collector.terminated() {
             self.term.write(if result {
                 &self.config.ok
+            } else if collector.allow_failure {
+                &self.config.warn
+            } else if collector.timed_out() {
+                &self.config.timed_out
             } else {
                 &self.config.ko
             });
+        } else if matches!(collector.last(), Progress::Queued) {
+            self.term.write("queued");
+        }
+        if let Some(duration) = collector.duration() {
+            self.term.write(&format!(" {}", format_duration(duration)));
         }
         self.term.clear_til_eol();
 
@@ -64,6 +74,12 @@ impl<'a> Display<'a> {
                         spin_len,
                     ));
                 }
+                InstructionState::TimedOut(instruction) => {
+                    self.term.write(&format!(
+                        "{:2$}    {} (timed out)",
+                        &self.config.timed_out, instruction, spin_len,
+                    ));
+                }
                 InstructionState::Running(instruction) => {
                     self.term.write(&format!("{}    {instruction}", self.spin));
                 }
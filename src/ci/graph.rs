@@ -0,0 +1,297 @@
+use crate::ci::config::{CliOption, Config, JobDesc};
+use crate::ci::job::dag::Dag;
+use crate::ci::job::schedule::select_jobs;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+    /// One job per line, followed by the jobs it waits for
+    #[default]
+    Text,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(Self::Dot),
+            "mermaid" => Ok(Self::Mermaid),
+            "text" => Ok(Self::Text),
+            _ => Err(format!(
+                "unknown format {s} (expected dot, mermaid or text)"
+            )),
+        }
+    }
+}
+
+struct Edge {
+    from: String,
+    to: String,
+    /// The edge comes from the group order, not from the constraints
+    implicit: bool,
+}
+
+/// The jobs of the ci and the order between them
+pub struct Graph {
+    jobs: Vec<JobDesc>,
+    groups: Vec<String>,
+    edges: Vec<Edge>,
+}
+
+impl Graph {
+    /// The graph of the jobs `qad ci` runs, leaving out the ones of groups missing from `groups`
+    pub fn new(config: &Config) -> Result<Self> {
+        let jobs = select_jobs(&CliOption::default(), config)?;
+
+        // reports unknown jobs and cycles like a run would
+        Dag::new(&jobs, &config.constraints, &config.groups)
+            .map_err(|error| error.locate(&config.origins))?;
+
+        let mut edges: Vec<Edge> = config
+            .constraints
+            .iter()
+            .map(|(from, to)| Edge {
+                from: from.clone(),
+                to: to.clone(),
+                implicit: false,
+            })
+            .collect();
+        for (from, to) in Dag::compute_group_constraints(&jobs, &config.groups) {
+            if !edges.iter().any(|edge| edge.from == from && edge.to == to) {
+                edges.push(Edge {
+                    from,
+                    to,
+                    implicit: true,
+                });
+            }
+        }
+
+        Ok(Self {
+            jobs: config
+                .jobs
+                .iter()
+                .filter(|desc| jobs.iter().any(|job| job.name() == desc.name))
+                .cloned()
+                .collect(),
+            groups: config.groups.clone(),
+            edges,
+        })
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.dot(),
+            GraphFormat::Mermaid => self.mermaid(),
+            GraphFormat::Text => self.text(),
+        }
+    }
+
+    fn members<'a>(&'a self, group: &'a String) -> impl Iterator<Item = &'a JobDesc> {
+        self.jobs
+            .iter()
+            .filter(move |job| job.group.first() == Some(group))
+    }
+
+    /// The jobs outside of the listed groups, all of them when `groups` is empty
+    fn ungrouped(&self) -> impl Iterator<Item = &JobDesc> {
+        self.jobs.iter().filter(|job| {
+            job.group
+                .first()
+                .is_none_or(|group| !self.groups.contains(group))
+        })
+    }
+
+    fn annotations(job: &JobDesc) -> Vec<String> {
+        let mut annotations = vec![];
+        if let Some(image) = &job.image {
+            annotations.push(format!("image: {}", image.image()));
+        }
+        if let Some(condition) = &job.skip_if {
+            annotations.push(format!("skip if: {condition}"));
+        }
+        annotations
+    }
+
+    fn dot(&self) -> String {
+        let escape = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+        let node = |job: &JobDesc| {
+            let mut label = escape(&job.name);
+            for annotation in Self::annotations(job) {
+                write!(label, "\\n{}", escape(&annotation)).expect("write");
+            }
+            format!("\"{}\" [label=\"{label}\"];", escape(&job.name))
+        };
+
+        let mut out = String::from("digraph qad {\n  rankdir=LR;\n  node [shape=box];\n");
+        for group in &self.groups {
+            writeln!(out, "  subgraph \"cluster_{}\" {{", escape(group)).expect("write");
+            writeln!(out, "    label=\"{}\";", escape(group)).expect("write");
+            for job in self.members(group) {
+                writeln!(out, "    {}", node(job)).expect("write");
+            }
+            out.push_str("  }\n");
+        }
+        for job in self.ungrouped() {
+            writeln!(out, "  {}", node(job)).expect("write");
+        }
+        for edge in &self.edges {
+            let style = if edge.implicit { " [style=dashed]" } else { "" };
+            writeln!(
+                out,
+                "  \"{}\" -> \"{}\"{style};",
+                escape(&edge.from),
+                escape(&edge.to)
+            )
+            .expect("write");
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn mermaid(&self) -> String {
+        // names may hold characters mermaid does not accept in ids
+        let ids: HashMap<&str, String> = self
+            .jobs
+            .iter()
+            .enumerate()
+            .map(|(index, job)| (job.name.as_str(), format!("job{index}")))
+            .collect();
+        let escape = |text: &str| text.replace('"', "#quot;");
+        let node = |job: &JobDesc| {
+            let mut label = escape(&job.name);
+            for annotation in Self::annotations(job) {
+                write!(label, "<br/>{}", escape(&annotation)).expect("write");
+            }
+            format!("{}[\"{label}\"]", ids[job.name.as_str()])
+        };
+
+        let mut out = String::from("flowchart LR\n");
+        for (index, group) in self.groups.iter().enumerate() {
+            writeln!(out, "  subgraph group{index} [\"{}\"]", escape(group)).expect("write");
+            for job in self.members(group) {
+                writeln!(out, "    {}", node(job)).expect("write");
+            }
+            out.push_str("  end\n");
+        }
+        for job in self.ungrouped() {
+            writeln!(out, "  {}", node(job)).expect("write");
+        }
+        for edge in &self.edges {
+            let arrow = if edge.implicit { "-.->" } else { "-->" };
+            writeln!(
+                out,
+                "  {} {arrow} {}",
+                ids[edge.from.as_str()],
+                ids[edge.to.as_str()]
+            )
+            .expect("write");
+        }
+        out
+    }
+
+    fn text(&self) -> String {
+        let mut out = String::new();
+        for job in &self.jobs {
+            let mut details = vec![];
+            if let Some(group) = job.group.first() {
+                details.push(format!("group: {group}"));
+            }
+            details.extend(Self::annotations(job));
+            if details.is_empty() {
+                writeln!(out, "{}", job.name).expect("write");
+            } else {
+                writeln!(out, "{} ({})", job.name, details.join(", ")).expect("write");
+            }
+
+            for edge in self.edges.iter().filter(|edge| edge.to == job.name) {
+                if edge.implicit {
+                    writeln!(out, "  after {} (group order)", edge.from).expect("write");
+                } else {
+                    writeln!(out, "  after {}", edge.from).expect("write");
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strvec;
+
+    fn config() -> Config {
+        let job = |name: &str, group: Option<&str>| JobDesc {
+            name: name.to_string(),
+            group: group.map(ToString::to_string).into_iter().collect(),
+            ..Default::default()
+        };
+        Config {
+            jobs: vec![
+                job("fmt", Some("check")),
+                JobDesc {
+                    skip_if: Some("test -z \"$CHANGED\"".to_string()),
+                    ..job("tests", Some("test"))
+                },
+                job("deploy", Some("release")),
+                job("docs", Some("unlisted")),
+            ],
+            groups: strvec!("check", "test", "release"),
+            constraints: vec![("tests".to_string(), "deploy".to_string())],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn text() {
+        assert_eq!(
+            "fmt (group: check)\n\
+             tests (group: test, skip if: test -z \"$CHANGED\")\n  after fmt (group order)\n\
+             deploy (group: release)\n  after tests\n  after fmt (group order)\n",
+            Graph::new(&config()).unwrap().render(GraphFormat::Text)
+        );
+    }
+
+    #[test]
+    pub fn dot() {
+        assert_eq!(
+            "digraph qad {\n  rankdir=LR;\n  node [shape=box];\n\
+             \x20 subgraph \"cluster_check\" {\n    label=\"check\";\n    \"fmt\" [label=\"fmt\"];\n  }\n\
+             \x20 subgraph \"cluster_test\" {\n    label=\"test\";\n    \"tests\" [label=\"tests\\nskip if: test -z \\\"$CHANGED\\\"\"];\n  }\n\
+             \x20 subgraph \"cluster_release\" {\n    label=\"release\";\n    \"deploy\" [label=\"deploy\"];\n  }\n\
+             \x20 \"tests\" -> \"deploy\";\n\
+             \x20 \"fmt\" -> \"tests\" [style=dashed];\n\
+             \x20 \"fmt\" -> \"deploy\" [style=dashed];\n}\n",
+            Graph::new(&config()).unwrap().render(GraphFormat::Dot)
+        );
+    }
+
+    #[test]
+    pub fn mermaid() {
+        assert_eq!(
+            "flowchart LR\n\
+             \x20 subgraph group0 [\"check\"]\n    job0[\"fmt\"]\n  end\n\
+             \x20 subgraph group1 [\"test\"]\n    job1[\"tests<br/>skip if: test -z #quot;$CHANGED#quot;\"]\n  end\n\
+             \x20 subgraph group2 [\"release\"]\n    job2[\"deploy\"]\n  end\n\
+             \x20 job1 --> job2\n\
+             \x20 job0 -.-> job1\n\
+             \x20 job0 -.-> job2\n",
+            Graph::new(&config()).unwrap().render(GraphFormat::Mermaid)
+        );
+    }
+
+    #[test]
+    pub fn cycles_are_reported() {
+        let mut config = config();
+        config
+            .constraints
+            .push(("deploy".to_string(), "fmt".to_string()));
+        assert!(Graph::new(&config).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use crate::ci::job::inspection::{JobProgressTracker, ProgressCollector};
+use crate::ci::job::Progress;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Where qad keeps its state between runs, relative to the working directory
+pub const STATE_DIR: &str = ".qad";
+const LAST_RUN: &str = "last_run.json";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failure,
+    TimedOut,
+    Cancelled,
+    Skipped,
+}
+
+impl Outcome {
+    /// Whether a rerun of the failed jobs should play the job again
+    pub const fn failed(self) -> bool {
+        matches!(self, Self::Failure | Self::TimedOut | Self::Cancelled)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub name: String,
+    pub outcome: Outcome,
+    pub duration_ms: Option<u128>,
+    pub output: String,
+}
+
+/// The results of the last `qad ci`, kept in the state directory
+#[derive(Debug, Serialize, Deserialize)]
+pub struct History {
+    pub jobs: Vec<JobRecord>,
+}
+
+impl History {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(LAST_RUN);
+        let content = fs::read_to_string(&path).map_err(|_| {
+            anyhow!(
+                "no previous run found in {}, run `qad ci` first",
+                path.display()
+            )
+        })?;
+        serde_json::from_str(&content)
+            .map_err(|error| anyhow!("could not read {}: {error}", path.display()))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(LAST_RUN), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Names of the jobs which failed, timed out or were cancelled
+    pub fn failed(&self) -> Vec<String> {
+        self.jobs
+            .iter()
+            .filter(|job| job.outcome.failed())
+            .map(|job| job.name.clone())
+            .collect()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.jobs.iter().map(|job| job.name.clone()).collect()
+    }
+
+    /// The records of `newer` replace the ones of the same jobs,
+    /// the jobs it did not run keep their previous record
+    pub fn merge(mut self, newer: Self) -> Self {
+        for record in newer.jobs {
+            match self.jobs.iter_mut().find(|job| job.name == record.name) {
+                Some(job) => *job = record,
+                None => self.jobs.push(record),
+            }
+        }
+        self
+    }
+}
+
+impl From<&JobProgressTracker> for History {
+    fn from(tracker: &JobProgressTracker) -> Self {
+        Self {
+            jobs: tracker
+                .states
+                .iter()
+                .map(|(name, collector)| JobRecord {
+                    name: name.clone(),
+                    outcome: outcome(collector),
+                    duration_ms: collector.duration().map(|duration| duration.as_millis()),
+                    output: output(collector),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn outcome(collector: &ProgressCollector) -> Outcome {
+    if matches!(collector.last(), Progress::Cancelled) {
+        Outcome::Cancelled
+    } else if collector
+        .progresses
+        .iter()
+        .any(|progress| matches!(progress, Progress::Skipped(_)))
+    {
+        Outcome::Skipped
+    } else if collector.timed_out() {
+        Outcome::TimedOut
+    } else {
+        match collector.terminated() {
+            Some(true) => Outcome::Success,
+            Some(false) => Outcome::Failure,
+            None => Outcome::Cancelled,
+        }
+    }
+}
+
+fn output(collector: &ProgressCollector) -> String {
+    let mut text = String::new();
+    for progress in &collector.progresses {
+        let (command, output) = match progress {
+            Progress::Partial(instruction, output) => (instruction.clone(), output),
+            Progress::Hook(hook, instruction, output) => {
+                (format!("[{hook}] {instruction}"), output)
+            }
+            Progress::ContainerStart(output) => ("[container] start".to_string(), output),
+            Progress::Retrying(attempt, attempts) => {
+                writeln!(text, "attempt {attempt}/{attempts}").expect("write");
+                continue;
+            }
+            _ => continue,
+        };
+        let (stdout, stderr) = output.streams();
+        writeln!(text, "$ {command}").expect("write");
+        text.push_str(stdout);
+        text.push_str(stderr);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::job::inspection::JobProgress;
+    use crate::ci::job::tests::temp_path;
+    use crate::ci::job::Output;
+
+    #[test]
+    pub fn failed_jobs_are_kept() {
+        let mut tracker = JobProgressTracker::new();
+        tracker.record(JobProgress::new(
+            "fmt",
+            Progress::Partial(
+                "cargo fmt".to_string(),
+                Output::Success("ok".to_string(), String::new()),
+            ),
+        ));
+        tracker.record(JobProgress::new("fmt", Progress::Terminated(true)));
+        tracker.record(JobProgress::new(
+            "tests",
+            Progress::Partial(
+                "cargo test".to_string(),
+                Output::JobError(String::new(), "boom".to_string(), 1),
+            ),
+        ));
+        tracker.record(JobProgress::new("tests", Progress::Terminated(false)));
+        tracker.record(JobProgress::cancel("deploy".to_string()));
+
+        let dir = temp_path("history");
+        History::from(&tracker).save(&dir).unwrap();
+        let history = History::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vec!["fmt", "tests", "deploy"], history.names());
+        assert_eq!(vec!["tests", "deploy"], history.failed());
+        assert_eq!(Outcome::Failure, history.jobs[1].outcome);
+        assert_eq!("$ cargo test\nboom", history.jobs[1].output);
+    }
+
+    #[test]
+    pub fn a_rerun_only_replaces_the_jobs_it_ran() {
+        let mut last_run = JobProgressTracker::new();
+        last_run.record(JobProgress::new("fmt", Progress::Terminated(true)));
+        last_run.record(JobProgress::new("tests", Progress::Terminated(false)));
+        let mut rerun = JobProgressTracker::new();
+        rerun.record(JobProgress::new("tests", Progress::Terminated(true)));
+
+        let history = History::from(&last_run).merge(History::from(&rerun));
+
+        assert_eq!(vec!["fmt", "tests"], history.names());
+        assert!(history.failed().is_empty());
+    }
+}
@@ -35,27 +35,28 @@ impl ConstraintMatrix {
 
         for constraint in constraints {
             if constraint.0 == constraint.1 {
-                return Err(Error::JobCannotBlockItself(constraint.1.to_string()));
+                return Err(Error::JobCannotBlockItself(constraint.1.clone()));
             }
             if !job_names.contains(&constraint.0) {
-                return Err(Error::UnknownJobInConstraint(constraint.0.to_string()));
+                return Err(Error::UnknownJobInConstraint(constraint.0.clone()));
             }
             if !job_names.contains(&constraint.1) {
-                return Err(Error::UnknownJobInConstraint(constraint.1.to_string()));
+                return Err(Error::UnknownJobInConstraint(constraint.1.clone()));
             }
         }
         for new_constraint in constraints {
             if let Some(cons) = matrix.get_mut(new_constraint) {
+                // only a job and itself are free, the cycles are looked for once the matrix is built
                 *cons = cons
                     .constrain()
-                    .map_err(|()| Error::CycleExistsBecauseOf(new_constraint.0.to_string()))?;
+                    .expect("a job blocking itself is rejected above");
             }
             if let Some(vec) = blocks_jobs.get_mut(&new_constraint.0) {
-                vec.insert(new_constraint.1.to_string());
+                vec.insert(new_constraint.1.clone());
             }
 
             if let Some(vec) = blocked_by_jobs.get_mut(&new_constraint.1) {
-                vec.insert(new_constraint.0.to_string());
+                vec.insert(new_constraint.0.clone());
             }
         }
 
@@ -65,6 +66,40 @@ impl ConstraintMatrix {
         })
     }
 
+    /// A path `a, b, …, a` along the constraints, when they loop
+    pub fn cycle(&self) -> Option<Vec<String>> {
+        let mut done = BTreeSet::new();
+        self.blocks_jobs
+            .keys()
+            .find_map(|job| self.find_cycle(job, &mut vec![], &mut done))
+    }
+
+    fn find_cycle(
+        &self,
+        job: &String,
+        path: &mut Vec<String>,
+        done: &mut BTreeSet<String>,
+    ) -> Option<Vec<String>> {
+        if let Some(position) = path.iter().position(|visited| visited == job) {
+            let mut cycle = path[position..].to_vec();
+            cycle.push(job.clone());
+            return Some(cycle);
+        }
+        if done.contains(job) {
+            return None;
+        }
+
+        path.push(job.clone());
+        for blocked in &self.blocks_jobs[job] {
+            if let Some(cycle) = self.find_cycle(blocked, path, done) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        done.insert(job.clone());
+        None
+    }
+
     pub fn blocked_by(&self, link: &str) -> ConstraintIterator {
         ConstraintIterator::new(&self.blocks_jobs, link.to_string())
     }
@@ -1,3 +1,33 @@
+use crate::ci::cache::digest;
+
+/// Every container is labelled with the pid of qad, to find them back when cancelling the ci
+const CONTAINER_LABEL: &str = "qad.pid";
+
+/// Every container is labelled with the process group of the instruction running it,
+/// to find it back when the instruction times out
+const INSTRUCTION_LABEL: &str = "qad.instruction";
+
+/// The containers living as long as their job carry this label
+const JOB_CONTAINER_LABEL: &str = "qad.lifetime=job";
+
+/// Every instruction run with `docker exec` gets the process group of the instruction
+/// running it in this variable, which its own processes inherit
+const INSTRUCTION_ENV: &str = "QAD_INSTRUCTION";
+
+/// Where the instructions of the job keep their state, between two of them
+const STATE_FILE: &str = "/tmp/qad-state";
+
+/// How long a container lives
+#[derive(Eq, PartialEq, Hash, Debug, Clone, Copy, Default)]
+pub enum Lifetime {
+    /// Each instruction runs in a container of its own
+    #[default]
+    Instruction,
+    /// The instructions of the job run one after the other in the same container,
+    /// keeping the files, the working directory and the exported variables of the previous ones
+    Job,
+}
+
 #[derive(Eq, PartialEq, Hash, Debug, Clone)]
 pub struct DockerContainer {
     image: String,
@@ -5,6 +35,7 @@ pub struct DockerContainer {
     volumes: Vec<String>,
     user: String,
     workdir: String,
+    lifetime: Lifetime,
 }
 
 impl DockerContainer {
@@ -32,19 +63,111 @@ impl DockerContainer {
         format!(r#"--workdir "{}""#, self.workdir)
     }
 
-    fn compile(&self, instruction: &str) -> String {
+    /// The shell running the instruction leads its process group, `$$` is its id
+    fn label() -> String {
         format!(
-            "docker run --rm {} {} {} {} {} {instruction}",
-            self.user(),
-            self.volumes(),
-            self.workdir(),
-            self.env(),
-            self.image,
+            r#"--label "{CONTAINER_LABEL}={}" --label "{INSTRUCTION_LABEL}=$$""#,
+            std::process::id()
         )
     }
 
+    /// The container of a job, unique to this qad process.
+    /// The hash tells apart the jobs whose names only differ by the replaced characters
+    fn name(job: &str) -> String {
+        let hash = digest(job);
+        let job: String = job
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || "_.-".contains(c) {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        format!("qad-{}-{job}-{}", std::process::id(), &hash[..8])
+    }
+
+    fn compile(&self, job: &str, instruction: &str) -> String {
+        match self.lifetime {
+            Lifetime::Instruction => format!(
+                "docker run --rm {} {} {} {} {} {} {instruction}",
+                Self::label(),
+                self.user(),
+                self.volumes(),
+                self.workdir(),
+                self.env(),
+                self.image,
+            ),
+            Lifetime::Job => {
+                // the state left by the previous instructions comes back, then the new one is saved
+                let script = format!(
+                    "[ -f {STATE_FILE} ] && . {STATE_FILE}\n{instruction}\nstatus=$?\n\
+                     export -p | grep -v \" {INSTRUCTION_ENV}=\" > {STATE_FILE}\n\
+                     echo \"cd \\\"$PWD\\\"\" >> {STATE_FILE}\nexit $status"
+                );
+                format!(
+                    r#"docker exec --env "{INSTRUCTION_ENV}=$$" "{}" sh -c '{}'"#,
+                    Self::name(job),
+                    script.replace('\'', r"'\''")
+                )
+            }
+        }
+    }
+
+    fn start(&self, job: &str) -> Option<String> {
+        match self.lifetime {
+            Lifetime::Instruction => None,
+            Lifetime::Job => Some(format!(
+                r#"docker run --detach --rm {} --label "{JOB_CONTAINER_LABEL}" {} {} {} {} --name "{}" --entrypoint tail {} -f /dev/null"#,
+                Self::label(),
+                self.user(),
+                self.volumes(),
+                self.workdir(),
+                self.env(),
+                Self::name(job),
+                self.image,
+            )),
+        }
+    }
+
+    fn stop(&self, job: &str) -> Option<String> {
+        match self.lifetime {
+            Lifetime::Instruction => None,
+            Lifetime::Job => Some(format!(r#"docker rm --force "{}""#, Self::name(job))),
+        }
+    }
+
+    /// Killing the docker client does not stop what it runs: the container of an instruction
+    /// is killed by label, the processes of an instruction run with `docker exec` by its variable
+    fn kill(&self, job: &str, instruction: u32) -> String {
+        match self.lifetime {
+            Lifetime::Instruction => format!(
+                r#"ids=$(docker ps --quiet --filter "label={CONTAINER_LABEL}={}" --filter "label={INSTRUCTION_LABEL}={instruction}") && [ -n "$ids" ] && docker kill $ids"#,
+                std::process::id()
+            ),
+            Lifetime::Job => format!(
+                r#"docker exec "{}" sh -c '{}'"#,
+                Self::name(job),
+                kill_instruction(instruction).replace('\'', r"'\''")
+            ),
+        }
+    }
+
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    pub const fn with_lifetime(mut self, lifetime: Lifetime) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
     pub fn forward_env(&mut self, key: &impl ToString) {
-        self.env.push(key.to_string());
+        let key = key.to_string();
+        if !self.env.contains(&key) {
+            self.env.push(key);
+        }
     }
 
     pub fn new(
@@ -60,36 +183,65 @@ impl DockerContainer {
             workdir: workdir.to_string(),
             user: user.to_string(),
             volumes: volumes.iter().map(ToString::to_string).collect(),
+            lifetime: Lifetime::default(),
         }
     }
 }
 
-#[derive(Eq, PartialEq, Hash, Debug, Clone)]
+/// The script killing, inside a container, the processes of the instruction
+/// whose process group is `instruction`
+fn kill_instruction(instruction: u32) -> String {
+    format!(
+        r#"for process in /proc/[0-9]*; do tr "\0" "\n" 2>/dev/null < "$process/environ" | grep -qx "{INSTRUCTION_ENV}={instruction}" && kill -9 "${{process#/proc/}}"; done"#
+    )
+}
+
+#[derive(Eq, PartialEq, Hash, Debug, Clone, Default)]
 pub enum ContainerConfiguration {
+    #[default]
     None,
     Container(DockerContainer),
 }
 
 impl ContainerConfiguration {
-    pub fn compile(&self, instruction: &str) -> String {
+    pub fn compile(&self, job: &str, instruction: &str) -> String {
         match self {
             ContainerConfiguration::None => instruction.to_string(),
-            ContainerConfiguration::Container(docker) => docker.compile(instruction),
+            ContainerConfiguration::Container(docker) => docker.compile(job, instruction),
         }
     }
-}
 
-impl Default for ContainerConfiguration {
-    fn default() -> Self {
-        Self::None
+    /// Starts the container of `job`, when it lives as long as the job
+    pub fn start(&self, job: &str) -> Option<String> {
+        match self {
+            ContainerConfiguration::None => None,
+            ContainerConfiguration::Container(docker) => docker.start(job),
+        }
+    }
+
+    /// Removes the container `start` started
+    pub fn stop(&self, job: &str) -> Option<String> {
+        match self {
+            ContainerConfiguration::None => None,
+            ContainerConfiguration::Container(docker) => docker.stop(job),
+        }
     }
-}
 
-impl ContainerConfiguration {}
+    /// Kills what the instruction of `job` whose process group is `instruction` runs in
+    /// a container, when it runs in one
+    pub fn kill(&self, job: &str, instruction: u32) -> Option<String> {
+        match self {
+            ContainerConfiguration::None => None,
+            ContainerConfiguration::Container(docker) => Some(docker.kill(job, instruction)),
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use super::DockerContainer;
+    use super::{kill_instruction, DockerContainer, Lifetime, INSTRUCTION_ENV};
+    use std::process::Command;
+    use std::time::{Duration, Instant};
 
     impl DockerContainer {
         fn test(image: &str, env: &[&str], volumes: &[&str], user: &str, workdir: &str) -> Self {
@@ -99,6 +251,7 @@ mod tests {
                 volumes: volumes.iter().map(ToString::to_string).collect(),
                 user: user.to_string(),
                 workdir: workdir.to_string(),
+                lifetime: Lifetime::Instruction,
             }
         }
     }
@@ -114,8 +267,76 @@ mod tests {
         );
 
         assert_eq!(
-            r#"docker run --rm --user "$USER_ID:$GROUP_ID" --volume "PWD:PWD:rw" --workdir "$PWD" --env "CHANGED_FILES" --env "HAS_RUST" rust:latest cargo fmt"#,
-            &container.compile("cargo fmt")
+            format!(
+                r#"docker run --rm --label "qad.pid={}" --label "qad.instruction=$$" --user "$USER_ID:$GROUP_ID" --volume "PWD:PWD:rw" --workdir "$PWD" --env "CHANGED_FILES" --env "HAS_RUST" rust:latest cargo fmt"#,
+                std::process::id()
+            ),
+            container.compile("fmt", "cargo fmt")
+        );
+        assert_eq!(
+            format!(
+                r#"ids=$(docker ps --quiet --filter "label=qad.pid={}" --filter "label=qad.instruction=42") && [ -n "$ids" ] && docker kill $ids"#,
+                std::process::id()
+            ),
+            container.kill("fmt", 42)
         );
     }
+
+    #[test]
+    fn one_container_per_job() {
+        let container =
+            DockerContainer::test("alpine", &[], &[], "1000", "/src").with_lifetime(Lifetime::Job);
+        let name = format!("qad-{}-tests-linux--fd753e69", std::process::id());
+
+        assert_eq!(
+            Some(format!(
+                r#"docker run --detach --rm --label "qad.pid={}" --label "qad.instruction=$$" --label "qad.lifetime=job" --user "1000"  --workdir "/src"  --name "{name}" --entrypoint tail alpine -f /dev/null"#,
+                std::process::id()
+            )),
+            container.start("tests[linux]")
+        );
+        assert_eq!(
+            format!(
+                r#"docker exec --env "QAD_INSTRUCTION=$$" "{name}" sh -c '[ -f /tmp/qad-state ] && . /tmp/qad-state
+echo '\''it works'\''
+status=$?
+export -p | grep -v " QAD_INSTRUCTION=" > /tmp/qad-state
+echo "cd \"$PWD\"" >> /tmp/qad-state
+exit $status'"#
+            ),
+            container.compile("tests[linux]", "echo 'it works'")
+        );
+        assert_eq!(
+            Some(format!(r#"docker rm --force "{name}""#)),
+            container.stop("tests[linux]")
+        );
+        assert!(container.kill("tests[linux]", 42).starts_with(&format!(
+            r#"docker exec "{name}" sh -c 'for process in /proc/"#
+        )));
+    }
+
+    #[test]
+    fn job_containers_have_their_own_name() {
+        assert_ne!(DockerContainer::name("a b"), DockerContainer::name("a-b"));
+    }
+
+    #[test]
+    #[ignore = "kills every process of the host carrying the variable, run it in a container"]
+    fn instructions_are_killed_by_their_variable() {
+        let start = Instant::now();
+        let mut instruction = Command::new("sh")
+            .args(["-c", "sleep 5"])
+            .env(INSTRUCTION_ENV, u32::MAX.to_string())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        Command::new("sh")
+            .args(["-c", &kill_instruction(u32::MAX)])
+            .status()
+            .unwrap();
+
+        assert!(!instruction.wait().unwrap().success());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
 }
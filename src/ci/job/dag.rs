@@ -5,9 +5,10 @@ use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Debug, Display, Formatter};
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub enum Constraint {
     Free,
+    #[default]
     Indifferent,
     Blocked(usize),
 }
@@ -22,17 +23,42 @@ impl Constraint {
     }
 }
 
-impl Default for Constraint {
-    fn default() -> Self {
-        Self::Indifferent
-    }
+/// Where an edge of the job DAG comes from
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EdgeOrigin {
+    /// `blocks` or `needs`, with the extra file declaring it, if any
+    Constraint(Option<String>),
+    /// The order of `groups`, from the group of the blocking job to the one of the blocked job
+    Groups(String, String),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub origin: EdgeOrigin,
 }
 
 #[derive(Debug)]
 pub enum Error {
     JobCannotBlockItself(String),
     UnknownJobInConstraint(String),
-    CycleExistsBecauseOf(String),
+    /// The edges of the cycle, each one starting where the previous one ends
+    Cycle(Vec<Edge>),
+}
+
+impl Error {
+    /// Names the extra files declaring the constraints of a cycle
+    pub fn locate(mut self, origins: &HashMap<(String, String), String>) -> Self {
+        if let Self::Cycle(edges) = &mut self {
+            for edge in edges {
+                if let EdgeOrigin::Constraint(file) = &mut edge.origin {
+                    *file = origins.get(&(edge.from.clone(), edge.to.clone())).cloned();
+                }
+            }
+        }
+        self
+    }
 }
 
 impl Display for Error {
@@ -44,8 +70,27 @@ impl Display for Error {
             Error::UnknownJobInConstraint(jobname) => {
                 write!(f, "job {jobname} in constraint list doesn't exist")
             }
-            Error::CycleExistsBecauseOf(blocking_job) => {
-                write!(f, "a cycle exists in the job DAG because of {blocking_job}")
+            Error::Cycle(edges) => {
+                write!(f, "a cycle exists in the job DAG: ")?;
+                for edge in edges {
+                    write!(f, "{} -> ", edge.from)?;
+                }
+                if let Some(last) = edges.last() {
+                    write!(f, "{}", last.to)?;
+                }
+                for edge in edges {
+                    write!(f, "\n  {} -> {}: ", edge.from, edge.to)?;
+                    match &edge.origin {
+                        EdgeOrigin::Constraint(None) => write!(f, "blocks/needs")?,
+                        EdgeOrigin::Constraint(Some(file)) => {
+                            write!(f, "blocks/needs in {file}")?;
+                        }
+                        EdgeOrigin::Groups(from, to) => {
+                            write!(f, "group {from} runs before group {to}")?;
+                        }
+                    }
+                }
+                Ok(())
             }
         }
     }
@@ -59,8 +104,9 @@ pub enum JobResult {
     Failure,
 }
 
-#[derive(Clone, PartialOrd, Ord, Eq, PartialEq)]
+#[derive(Clone, PartialOrd, Ord, Eq, PartialEq, Default)]
 pub enum JobState {
+    #[default]
     Pending,
     Started,
     Blocked,
@@ -91,12 +137,6 @@ impl Debug for JobState {
     }
 }
 
-impl Default for JobState {
-    fn default() -> Self {
-        Self::Pending
-    }
-}
-
 #[derive(Debug)]
 pub struct JobList {
     vec: Vec<String>,
@@ -152,6 +192,9 @@ impl JobWatcher {
 pub struct Dag {
     all_jobs: BTreeMap<String, JobWatcher>,
     available_jobs: JobList,
+    /// The jobs each job blocks through `blocks` or `needs`, leaving out the group order
+    explicit_blocks: BTreeMap<String, Vec<String>>,
+    keep_going: bool,
 }
 
 pub struct JobEnumeration {
@@ -195,29 +238,26 @@ impl Dag {
         jobs: &[Job],
         constraints: &[(String, String)],
         groups: &[String],
-        env: &HashMap<String, Vec<String>>,
     ) -> Result<Self, Error> {
         let jobs: Vec<Job> = jobs.to_vec();
+        let explicit = constraints;
         let mut constraints: Vec<(String, String)> = constraints.to_vec();
 
         constraints.extend(Self::compute_group_constraints(&jobs, groups));
 
         let matrix = ConstraintMatrix::new(&jobs, &constraints)?;
+        if let Some(cycle) = matrix.cycle() {
+            return Err(Error::Cycle(Self::edges(&cycle, explicit, &jobs)));
+        }
 
         let mut all_jobs = BTreeMap::<String, JobWatcher>::new();
 
-        for mut job in jobs {
-            job.forward_env(env);
+        for job in jobs {
             let blocking = matrix.blocked_by(job.name());
             let blocked_by_jobs: Vec<String> = matrix.blocking(job.name()).collect();
             let state = if blocked_by_jobs.is_empty() {
                 JobState::Pending
             } else {
-                for blocked_by_job in &blocked_by_jobs {
-                    if blocked_by_job == job.name() {
-                        return Err(Error::CycleExistsBecauseOf(blocked_by_job.clone()));
-                    }
-                }
                 JobState::Blocked
             };
             all_jobs.insert(
@@ -231,9 +271,19 @@ impl Dag {
             );
         }
 
+        let mut explicit_blocks = BTreeMap::<String, Vec<String>>::new();
+        for (blocker, blocked) in explicit {
+            explicit_blocks
+                .entry(blocker.clone())
+                .or_default()
+                .push(blocked.clone());
+        }
+
         let mut dag = Self {
             all_jobs,
             available_jobs: JobList::new(),
+            explicit_blocks,
+            keep_going: false,
         };
 
         dag.actualize_job_list();
@@ -241,12 +291,66 @@ impl Dag {
         Ok(dag)
     }
 
-    fn compute_group_constraints(jobs: &[Job], groups: &[String]) -> Vec<(String, String)> {
+    /// A failure only cancels the jobs which need the failed one through `blocks` or `needs`,
+    /// the jobs coming after it in the group order run anyway
+    pub const fn keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// The edges along a path of jobs, telling the constraints from the group order
+    fn edges(path: &[String], explicit: &[(String, String)], jobs: &[Job]) -> Vec<Edge> {
+        let group = |name: &str| {
+            jobs.iter()
+                .find(|job| job.name() == name)
+                .and_then(Job::group)
+                .unwrap_or_default()
+                .to_string()
+        };
+        path.windows(2)
+            .map(|pair| {
+                let (from, to) = (pair[0].clone(), pair[1].clone());
+                let origin = if explicit.contains(&(from.clone(), to.clone())) {
+                    EdgeOrigin::Constraint(None)
+                } else {
+                    EdgeOrigin::Groups(group(&from), group(&to))
+                };
+                Edge { from, to, origin }
+            })
+            .collect()
+    }
+
+    /// Jobs which must run before the `selected` ones, directly or not,
+    /// because of the constraints or the group ordering
+    pub fn prerequisites(
+        jobs: &[Job],
+        constraints: &[(String, String)],
+        groups: &[String],
+        selected: &[String],
+    ) -> Result<Vec<String>, Error> {
+        let mut constraints: Vec<(String, String)> = constraints.to_vec();
+        constraints.extend(Self::compute_group_constraints(jobs, groups));
+
+        let matrix = ConstraintMatrix::new(jobs, &constraints)?;
+
+        let mut prerequisites = vec![];
+        for name in selected {
+            for prerequisite in matrix.blocking(name) {
+                if !selected.contains(&prerequisite) && !prerequisites.contains(&prerequisite) {
+                    prerequisites.push(prerequisite);
+                }
+            }
+        }
+        Ok(prerequisites)
+    }
+
+    /// Each job of a group waits for the jobs of the groups before it
+    pub fn compute_group_constraints(jobs: &[Job], groups: &[String]) -> Vec<(String, String)> {
         let mut group_constraints = vec![];
         let mut blocking_jobs_by_groups = IndexMap::<String, Vec<String>>::new();
 
         for group in groups {
-            blocking_jobs_by_groups.insert(group.to_string(), vec![]);
+            blocking_jobs_by_groups.insert(group.clone(), vec![]);
         }
 
         for job in jobs {
@@ -264,7 +368,7 @@ impl Dag {
                         break;
                     }
                     for blocking_job in blocking_job {
-                        group_constraints.push((blocking_job.to_string(), job.name().to_string()));
+                        group_constraints.push((blocking_job.clone(), job.name().to_string()));
                     }
                 }
             }
@@ -301,8 +405,9 @@ impl Dag {
     /// inform the Dag of the result of the job `job`
     /// with `result` either:
     /// `JobResult::Failure` or `JobResult::Success`
+    /// A failure of a job allowed to fail counts as a success for the next jobs
     pub fn record_event(&mut self, job: &str, result: JobResult) {
-        let job_went_wrong = matches!(result, JobResult::Failure);
+        let mut job_went_wrong = matches!(result, JobResult::Failure);
 
         if let Some(watcher) = self.all_jobs.get_mut(job) {
             if !matches!(watcher.state, JobState::Started) {
@@ -314,11 +419,17 @@ impl Dag {
                 )
             }
 
+            job_went_wrong &= !watcher.job.allows_failure();
             watcher.state = JobState::Terminated(result);
         } else {
             unreachable!("recorded job not in all_jobs");
         }
 
+        if job_went_wrong && self.keep_going {
+            self.cancel_needing_jobs(job);
+            self.actualize_job_list();
+            return;
+        }
         if job_went_wrong {
             self.cancel_next_jobs(job);
             return;
@@ -328,6 +439,17 @@ impl Dag {
         self.actualize_job_list();
     }
 
+    /// Cancels every job that has not been polled yet,
+    /// jobs already started still have to be recorded with `record_event`
+    pub fn cancel_pending(&mut self) {
+        for watcher in self.all_jobs.values_mut() {
+            if matches!(watcher.state, JobState::Pending | JobState::Blocked) {
+                watcher.state = JobState::Cancelled(vec![]);
+            }
+        }
+        self.available_jobs = JobList::new();
+    }
+
     /// A query method to know if all possible jobs have been ran
     pub fn is_finished(&self) -> bool {
         for job in self.all_jobs.values() {
@@ -375,7 +497,7 @@ impl Dag {
         for blocked_job_name in blocked_job_list {
             let blocked_job = self.all_jobs.get_mut(blocked_job_name.as_str()).unwrap();
             if matches!(blocked_job.state, JobState::Cancelled(_)) {
-                return;
+                continue;
             }
             debug_assert!(
                 matches!(blocked_job.state, JobState::Blocked),
@@ -417,11 +539,51 @@ impl Dag {
             blocked_job.state = JobState::Cancelled(list);
         }
     }
+
+    /// Cancels the jobs needing `name` through explicit constraints, directly or not.
+    /// For the other jobs, `name` and the cancelled jobs count as done
+    fn cancel_needing_jobs(&mut self, name: &str) {
+        let mut cancelled: Vec<String> = vec![];
+        let mut stack = vec![name.to_string()];
+        while let Some(current) = stack.pop() {
+            for needing in self.explicit_blocks.get(&current).into_iter().flatten() {
+                if !cancelled.contains(needing) {
+                    cancelled.push(needing.clone());
+                    stack.push(needing.clone());
+                }
+            }
+        }
+
+        for needing in &cancelled {
+            let watcher = self.all_jobs.get_mut(needing).unwrap();
+            match &mut watcher.state {
+                JobState::Pending | JobState::Blocked => {
+                    watcher.state = JobState::Cancelled(vec![name.to_string()]);
+                }
+                JobState::Cancelled(by) => by.push(name.to_string()),
+                // a job already running or done is left to finish
+                JobState::Started | JobState::Terminated(_) => {}
+            }
+        }
+
+        for watcher in self.all_jobs.values_mut() {
+            if !matches!(watcher.state, JobState::Blocked) {
+                continue;
+            }
+            watcher.blocked_by_jobs.remove_job(name);
+            for done in &cancelled {
+                watcher.blocked_by_jobs.remove_job(done);
+            }
+            if watcher.blocked_by_jobs.is_empty() {
+                watcher.state = JobState::Pending;
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::ci::job::dag::{Dag, Error, JobEnumeration, JobList, JobResult};
+    use crate::ci::job::dag::{Dag, JobEnumeration, JobList, JobResult};
     use crate::ci::job::tests::{
         complex_job_schedule, cons, group_job_schedule, job, simple_job_schedule,
     };
@@ -449,10 +611,26 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn prerequisites() {
+        let (jobs, constraints, groups) = complex_job_schedule();
+
+        let mut prerequisites =
+            Dag::prerequisites(&jobs, &constraints, &groups, &["test1".to_string()]).unwrap();
+        prerequisites.sort();
+        assert_eq!(vec!["build1", "build2"], prerequisites);
+
+        let (jobs, constraints, groups) = group_job_schedule();
+        let mut prerequisites =
+            Dag::prerequisites(&jobs, &constraints, &groups, &["test2".to_string()]).unwrap();
+        prerequisites.sort();
+        assert_eq!(vec!["build1", "build2"], prerequisites);
+    }
+
     #[test]
     pub fn record_good() {
         let (jobs, constraints, groups) = simple_job_schedule();
-        let mut dag = Dag::new(&jobs, &constraints, &groups, &HashMap::new()).unwrap();
+        let mut dag = Dag::new(&jobs, &constraints, &groups).unwrap();
         let build = dag.poll().expect("this is not None");
 
         assert_eq!("build", build.name());
@@ -470,7 +648,7 @@ mod tests {
     #[test]
     pub fn record_bad() {
         let (jobs, constraints, groups) = simple_job_schedule();
-        let mut dag = Dag::new(&jobs, &constraints, &groups, &HashMap::new()).unwrap();
+        let mut dag = Dag::new(&jobs, &constraints, &groups).unwrap();
 
         let job = dag.poll().expect("this is not None");
 
@@ -484,14 +662,14 @@ mod tests {
     #[test]
     pub fn test_complex() {
         let (jobs, constraints, groups) = complex_job_schedule();
-        let mut dag = Dag::new(&jobs, &constraints, &groups, &HashMap::new()).unwrap();
+        let mut dag = Dag::new(&jobs, &constraints, &groups).unwrap();
         full_dag_test(&mut dag);
     }
 
     #[test]
     pub fn test_group() {
         let (jobs, constraints, groups) = group_job_schedule();
-        let mut dag = Dag::new(&jobs, &constraints, &groups, &HashMap::new()).unwrap();
+        let mut dag = Dag::new(&jobs, &constraints, &groups).unwrap();
         full_dag_test(&mut dag);
     }
 
@@ -524,10 +702,53 @@ mod tests {
         assert!(dag.is_finished());
     }
 
+    #[test]
+    pub fn success_after_a_cancellation_unlocks_the_other_jobs() {
+        let jobs = vec![job("a"), job("b"), job("c"), job("d")];
+        let constraints = vec![cons("a", "c"), cons("b", "c"), cons("b", "d")];
+        let mut dag = Dag::new(&jobs, &constraints, &[]).unwrap();
+
+        let a = dag.poll().unwrap();
+        let b = dag.poll().unwrap();
+        dag.record_event(a.name(), JobResult::Failure);
+        dag.record_event(b.name(), JobResult::Success);
+
+        assert_eq!("d", dag.poll().unwrap().name());
+    }
+
+    #[test]
+    pub fn keep_going() {
+        let (jobs, _, groups) = group_job_schedule();
+        let constraints = vec![cons("build1", "test2")];
+        let mut dag = Dag::new(&jobs, &constraints, &groups)
+            .unwrap()
+            .keep_going(true);
+
+        let build1 = dag.poll().unwrap();
+        let build2 = dag.poll().unwrap();
+        dag.record_event(build1.name(), JobResult::Failure);
+        dag.record_event(build2.name(), JobResult::Success);
+
+        let test1 = dag.poll().unwrap();
+        assert_eq!("test1", test1.name());
+        assert!(dag.poll().is_none());
+        dag.record_event(test1.name(), JobResult::Success);
+
+        let deploy = dag.poll().unwrap();
+        assert_eq!("deploy", deploy.name());
+        dag.record_event(deploy.name(), JobResult::Success);
+
+        assert!(dag.is_finished());
+        assert_eq!(
+            "[build1(failure), build2(success), deploy(success), test1(success), test2(cancelled)]",
+            format!("{:?}", dag.enumerate())
+        );
+    }
+
     #[test]
     pub fn test_enumerate_base() {
         let (jobs, constraints, groups) = complex_job_schedule();
-        let dag = Dag::new(&jobs, &constraints, &groups, &HashMap::new()).unwrap();
+        let dag = Dag::new(&jobs, &constraints, &groups).unwrap();
         let actual = dag.enumerate();
         assert_eq!(
             String::from("[build1(pending), build2(pending), test1(blocked), test2(blocked), deploy(blocked)]"),
@@ -538,7 +759,7 @@ mod tests {
     #[test]
     pub fn test_enumerate_failure() {
         let (jobs, constraints, groups) = complex_job_schedule();
-        let mut dag = Dag::new(&jobs, &constraints, &groups, &HashMap::new()).unwrap();
+        let mut dag = Dag::new(&jobs, &constraints, &groups).unwrap();
 
         dag.poll();
         dag.record_event("build1", JobResult::Failure);
@@ -559,19 +780,56 @@ mod tests {
             format!("{actual:?}"));
     }
 
+    #[test]
+    pub fn test_cancel_pending() {
+        let (jobs, constraints, groups) = complex_job_schedule();
+        let mut dag = Dag::new(&jobs, &constraints, &groups).unwrap();
+
+        let build1 = dag.poll().unwrap();
+        dag.cancel_pending();
+
+        assert!(dag.poll().is_none());
+        assert!(!dag.is_finished());
+
+        dag.record_event(build1.name(), JobResult::Failure);
+
+        assert!(dag.is_finished());
+        assert_eq!(
+            String::from("[build1(failure), build2(cancelled), test1(cancelled), test2(cancelled), deploy(cancelled)]"),
+            format!("{:?}", dag.enumerate())
+        );
+    }
+
     #[test]
     pub fn test_cycle() {
         let jobs = vec![job("A"), job("B"), job("C")];
         let cons = vec![cons("A", "B"), cons("B", "C"), cons("C", "A")];
-        let error = Dag::new(&jobs, &cons, &[], &HashMap::new()).err().unwrap();
+        let error = Dag::new(&jobs, &cons, &[]).err().unwrap();
 
-        if let Error::CycleExistsBecauseOf(letter) = error {
-            assert_eq!(&letter, "A");
-        } else {
-            panic!(
-                "{error:?} should be a {:?}",
-                Error::CycleExistsBecauseOf(String::from("A"))
-            )
-        }
+        assert_eq!(
+            "a cycle exists in the job DAG: A -> B -> C -> A\n  \
+             A -> B: blocks/needs\n  \
+             B -> C: blocks/needs\n  \
+             C -> A: blocks/needs",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    pub fn test_cycle_through_groups() {
+        let (jobs, _, groups) = group_job_schedule();
+        let cons = vec![cons("deploy", "build2")];
+        let origins = HashMap::from([(cons[0].clone(), "deploy.yaml".to_string())]);
+        let error = Dag::new(&jobs, &cons, &groups)
+            .err()
+            .unwrap()
+            .locate(&origins);
+
+        assert_eq!(
+            "a cycle exists in the job DAG: deploy -> build2 -> deploy\n  \
+             deploy -> build2: blocks/needs in deploy.yaml\n  \
+             build2 -> deploy: group build runs before group deploy",
+            error.to_string()
+        );
     }
 }
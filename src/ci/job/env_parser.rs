@@ -1,5 +1,10 @@
 use crate::ci::job::env_parser::Capturing::ValueWithKey;
+use regex::Regex;
 use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// The names a shell accepts for a variable
+static KEY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap());
 
 #[derive(Debug)]
 enum Capturing {
@@ -23,14 +28,14 @@ pub fn parse_env_into_map<T: Into<String>>(env_string: T) -> HashMap<String, Vec
             (ValueWithKey(key), '=') => {
                 if last_symbol_start < last_new_line {
                     map.insert(
-                        key.to_string(),
+                        key.clone(),
                         env_string[last_symbol_start..last_new_line]
                             .split('\n')
                             .map(ToString::to_string)
                             .collect(),
                     );
                 } else {
-                    map.insert(key.to_string(), vec![]);
+                    map.insert(key.clone(), vec![]);
                 }
                 capturing = ValueWithKey(env_string[last_new_line + 1..pos].to_string());
                 last_symbol_start = pos + 1;
@@ -52,6 +57,62 @@ pub fn parse_env_into_map<T: Into<String>>(env_string: T) -> HashMap<String, Vec
     map
 }
 
+/// Reads a dotenv file: `KEY=value` lines, optionally prefixed with `export`,
+/// blank lines and `#` comments. Quotes around a value are removed,
+/// and double quoted values may hold `\n`, `\"` and `\\`
+pub fn parse_dotenv(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut env = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected KEY=value", index + 1));
+        };
+        let key = key.trim();
+        if !KEY.is_match(key) {
+            return Err(format!("line {}: invalid variable name {key}", index + 1));
+        }
+        let value = value.trim();
+        let value = if let Some(quoted) = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+        {
+            unescape(quoted)
+        } else if let Some(quoted) = value
+            .strip_prefix('\'')
+            .and_then(|value| value.strip_suffix('\''))
+        {
+            quoted.to_string()
+        } else {
+            value.to_string()
+        };
+        env.push((key.to_string(), value));
+    }
+    Ok(env)
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(char) = chars.next() {
+        match (char, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some(escaped @ ('"' | '\\'))) => {
+                unescaped.push(escaped);
+                chars.next();
+            }
+            _ => unescaped.push(char),
+        }
+    }
+    unescaped
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,4 +159,37 @@ mod tests {
             strvec!("KEY1: []", "KEY2: [value]").join("")
         );
     }
+
+    #[test]
+    pub fn dotenv() {
+        let env = parse_dotenv(
+            "# local settings\n\nexport RUST_LOG=debug\nNAME = \"two\\nlines\"\nRAW='$HOME'\nEMPTY=\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                ("RUST_LOG".to_string(), "debug".to_string()),
+                ("NAME".to_string(), "two\nlines".to_string()),
+                ("RAW".to_string(), "$HOME".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ],
+            env
+        );
+    }
+
+    #[test]
+    pub fn dotenv_errors() {
+        assert_eq!(
+            Err("line 2: expected KEY=value".to_string()),
+            parse_dotenv("A=1\noops")
+        );
+        assert_eq!(
+            Err("line 1: invalid variable name 1A".to_string()),
+            parse_dotenv("1A=1")
+        );
+        assert_eq!(
+            Err("line 1: invalid variable name CAFÉ".to_string()),
+            parse_dotenv("CAFÉ=1")
+        );
+    }
 }
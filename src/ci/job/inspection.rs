@@ -1,15 +1,16 @@
-use crate::ci::job::Progress;
+use crate::ci::job::{Hook, Output, Progress};
 use indexmap::IndexMap;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
-pub struct JobProgress(String, pub Progress);
+/// A progress of a job, stamped with the time it happened
+pub struct JobProgress(String, pub Progress, SystemTime);
 
 impl JobProgress {
     pub fn new(job_name: &str, progress: Progress) -> Self {
-        Self(job_name.to_string(), progress)
+        Self(job_name.to_string(), progress, SystemTime::now())
     }
-    pub const fn cancel(job_name: String) -> Self {
-        Self(job_name, Progress::Cancelled)
+    pub fn cancel(job_name: String) -> Self {
+        Self(job_name, Progress::Cancelled, SystemTime::now())
     }
 
     pub fn name(&self) -> &str {
@@ -21,14 +22,61 @@ impl JobProgress {
     }
 }
 
-#[derive(Default)]
+/// How many of the last lines written by the running instruction are kept
+const TAIL_SIZE: usize = 5;
+
+#[derive(Default, Clone)]
 pub struct ProgressCollector {
     pub progresses: Vec<Progress>,
+    /// When each of the progresses happened
+    pub timestamps: Vec<SystemTime>,
+    /// The lines written by the instructions, kept apart from the progresses
+    pub lines: Vec<(SystemTime, String)>,
+    /// Where the lines of the current instruction begin
+    tail_start: usize,
+    /// When a runner took the job, before its container, its hooks and its instructions
+    started: Option<SystemTime>,
+    /// The job may fail without failing the ci
+    pub allow_failure: bool,
 }
 
 impl ProgressCollector {
-    fn push(&mut self, progress: Progress) {
+    fn push(&mut self, progress: Progress, time: SystemTime) {
+        match progress {
+            Progress::Line(line) => {
+                self.lines.push((time, line));
+                return;
+            }
+            Progress::Started(_) => self.tail_start = self.lines.len(),
+            _ => {}
+        }
         self.progresses.push(progress);
+        self.timestamps.push(time);
+    }
+
+    /// The progresses along with the time they happened
+    pub fn timed(&self) -> impl Iterator<Item = (SystemTime, &Progress)> {
+        self.timestamps.iter().copied().zip(&self.progresses)
+    }
+
+    /// How long the job has been running, or ran if it is terminated, from the moment
+    /// a runner took it: the start of its container and its hooks count.
+    /// None if it never started
+    pub fn duration(&self) -> Option<Duration> {
+        let start = self.started?;
+        let end = self
+            .timed()
+            .find(|(_, progress)| matches!(progress, Progress::Terminated(_)))
+            .map_or_else(SystemTime::now, |(time, _)| time);
+        end.duration_since(start).ok()
+    }
+
+    /// The last lines written by the current instruction
+    pub fn tail(&self) -> impl Iterator<Item = &str> {
+        let start = self
+            .tail_start
+            .max(self.lines.len().saturating_sub(TAIL_SIZE));
+        self.lines[start..].iter().map(|(_, line)| line.as_str())
     }
 
     pub fn last(&self) -> &Progress {
@@ -42,11 +90,31 @@ impl ProgressCollector {
         }
     }
 
+    /// Whether the job failed, but was allowed to
+    pub fn failure_allowed(&self) -> bool {
+        self.allow_failure && self.terminated() == Some(false)
+    }
+
+    /// Whether the last attempt at an instruction timed out, the retried ones do not count
+    pub fn timed_out(&self) -> bool {
+        self.progresses
+            .iter()
+            .rev()
+            .find(|progress| matches!(progress, Progress::Partial(_, _)))
+            .is_some_and(|progress| {
+                matches!(progress, Progress::Partial(_, Output::TimedOut(_, _)))
+            })
+    }
+
     pub fn instruction_list(&self) -> Vec<InstructionState> {
         let mut vec = vec![];
         let mut temp = None;
         for progress in &self.progresses {
             match progress {
+                Progress::Partial(instruction, Output::TimedOut(_, _)) => {
+                    temp = None;
+                    vec.push(InstructionState::TimedOut(instruction.clone()));
+                }
                 Progress::Partial(instruction, output) => {
                     temp = None;
                     vec.push(InstructionState::Finished(
@@ -71,14 +139,21 @@ impl ProgressCollector {
 
 pub enum InstructionState {
     Finished(String, bool),
+    TimedOut(String),
     Running(String),
 }
 
+#[derive(Clone)]
 pub struct JobProgressTracker {
     pub start_time: SystemTime,
     pub end_time: Option<SystemTime>,
     pub states: IndexMap<String, ProgressCollector>,
+    /// The setup and teardown of the ci
+    pub hooks: ProgressCollector,
     pub has_failed: bool,
+    /// Whether a job allowed to fail did
+    pub has_allowed_failures: bool,
+    pub interrupted: bool,
 }
 
 impl JobProgressTracker {
@@ -95,15 +170,49 @@ impl JobProgressTracker {
             start_time: SystemTime::now(),
             end_time: None,
             states: IndexMap::new(),
+            hooks: ProgressCollector::default(),
             has_failed: false,
+            has_allowed_failures: false,
+            interrupted: false,
         }
     }
-    pub fn record(&mut self, job_progress: JobProgress) {
-        self.has_failed |= job_progress.failed();
+
+    pub const fn interrupt(&mut self) {
+        self.interrupted = true;
+        self.has_failed = true;
+    }
+    /// The failures of `job` will not fail the ci
+    pub fn allow_failure(&mut self, job: &str) {
         self.states
-            .entry(job_progress.0)
+            .entry(job.to_string())
             .or_default()
-            .push(job_progress.1);
+            .allow_failure = true;
+    }
+
+    /// The job is handed to a runner, its duration starts
+    pub fn start(&mut self, job: &str) {
+        self.states.entry(job.to_string()).or_default().started = Some(SystemTime::now());
+    }
+
+    pub fn record(&mut self, job_progress: JobProgress) {
+        let failed = job_progress.failed();
+        let collector = self.states.entry(job_progress.0).or_default();
+        if failed {
+            if collector.allow_failure {
+                self.has_allowed_failures = true;
+            } else {
+                self.has_failed = true;
+            }
+        }
+        collector.push(job_progress.1, job_progress.2);
+    }
+
+    /// Records the output of an instruction of the setup or the teardown
+    pub fn record_hook(&mut self, hook: Hook, instruction: &str, output: Output) {
+        self.hooks.push(
+            Progress::Hook(hook, instruction.to_string(), output),
+            SystemTime::now(),
+        );
     }
 
     pub fn finish(&mut self) {
@@ -112,3 +221,31 @@ impl JobProgressTracker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn duration() {
+        let mut tracker = JobProgressTracker::new();
+        tracker.record(JobProgress::new("build", Progress::Queued));
+        assert_eq!(None, tracker.states["build"].duration());
+
+        tracker.start("build");
+        tracker.record(JobProgress::new(
+            "build",
+            Progress::Started("make".to_string()),
+        ));
+        let running = tracker.states["build"].duration().unwrap();
+        assert!(tracker.states["build"].duration().unwrap() >= running);
+
+        tracker.record(JobProgress::new("build", Progress::Terminated(true)));
+        let ran = tracker.states["build"].duration().unwrap();
+        assert_eq!(Some(ran), tracker.states["build"].duration());
+        assert_eq!(
+            tracker.states["build"].progresses.len(),
+            tracker.states["build"].timestamps.len()
+        );
+    }
+}
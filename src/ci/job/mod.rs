@@ -5,6 +5,7 @@ mod env_parser;
 pub mod inspection;
 pub mod ports;
 pub mod schedule;
+pub mod secrets;
 pub mod shell_interpreter;
 #[cfg(test)]
 pub mod tests;
@@ -14,38 +15,146 @@ use crate::ci::job::inspection::JobProgressTracker;
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Output {
     Success(String, String),
-    JobError(String, String),
+    /// The instruction ran, but exited with the given code
+    JobError(String, String, i32),
     ProcessError(String),
+    TimedOut(String, String),
 }
 
 impl Output {
     pub const fn succeeded(&self) -> bool {
         matches!(self, Output::Success(_, _))
     }
+
+    /// Its stdout and its stderr, an instruction which could not run only has the latter
+    pub fn streams(&self) -> (&str, &str) {
+        match self {
+            Output::Success(stdout, stderr)
+            | Output::JobError(stdout, stderr, _)
+            | Output::TimedOut(stdout, stderr) => (stdout, stderr),
+            Output::ProcessError(stderr) => ("", stderr),
+        }
+    }
+
+    pub const fn failure(&self) -> Option<Failure> {
+        match self {
+            Output::Success(_, _) => None,
+            Output::JobError(_, _, _) => Some(Failure::JobError),
+            Output::ProcessError(_) => Some(Failure::ProcessError),
+            Output::TimedOut(_, _) => Some(Failure::TimedOut),
+        }
+    }
+}
+
+/// The ways an instruction fails
+#[derive(Eq, PartialEq, Hash, Debug, Clone, Copy)]
+pub enum Failure {
+    JobError,
+    ProcessError,
+    TimedOut,
+}
+
+/// How many times, and on which failures, a failing instruction runs again
+#[derive(Eq, PartialEq, Hash, Debug, Clone, Default)]
+pub struct Retry {
+    pub max: u32,
+    pub when: Vec<Failure>,
+}
+
+impl Retry {
+    pub const fn none() -> Self {
+        Self {
+            max: 0,
+            when: vec![],
+        }
+    }
+
+    fn covers(&self, output: &Output) -> bool {
+        output
+            .failure()
+            .is_some_and(|failure| self.when.contains(&failure))
+    }
+}
+
+/// Scripts run apart from the instructions, around a job or around the whole ci
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Hook {
+    Setup,
+    BeforeScript,
+    AfterScript,
+    Teardown,
+}
+
+impl Display for Hook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Hook::Setup => "setup",
+            Hook::BeforeScript => "before_script",
+            Hook::AfterScript => "after_script",
+            Hook::Teardown => "teardown",
+        })
+    }
+}
+
+/// The instructions of a hook, and whether their failure fails the job or the ci
+#[derive(Eq, PartialEq, Hash, Debug, Clone, Default)]
+pub struct HookScript {
+    pub instructions: Vec<String>,
+    pub fatal: bool,
+}
+
+impl HookScript {
+    pub const fn none() -> Self {
+        Self {
+            instructions: vec![],
+            fatal: false,
+        }
+    }
+
+    /// Runs the instructions until one fails, reporting the output of each.
+    /// Whether the hook succeeded, or failed without being fatal
+    pub fn run(&self, run: impl Fn(&str) -> Output, mut report: impl FnMut(&str, Output)) -> bool {
+        for instruction in &self.instructions {
+            let output = run(instruction);
+            let success = output.succeeded();
+            report(instruction, output);
+            if !success {
+                return !self.fatal;
+            }
+        }
+        true
+    }
 }
 
 pub trait ProgressConsumer {
     fn consume(&self, job_progress: JobProgress);
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Progress {
     Available,
+    Queued,
     Blocked(Vec<String>),
     Cancelled,
     Started(String),
+    /// A line written by the running instruction, its full output comes with `Partial`
+    Line(String),
     Partial(String, Output),
-    Skipped,
+    /// The instruction which just failed runs again, for attempt `.0` out of `.1` attempts
+    Retrying(u32, u32),
+    /// The output of an instruction of a hook
+    Hook(Hook, String, Output),
+    /// The output of starting the container of the job, when it lives as long as the job
+    ContainerStart(Output),
+    /// The job did not run, for the given reason
+    Skipped(String),
     Terminated(bool),
 }
 
 impl Progress {
+    /// Whether the job ended in failure, a failed instruction may still be retried
     pub const fn failed(&self) -> bool {
-        matches!(
-            self,
-            Progress::Partial(_, Output::JobError(_, _) | Output::ProcessError(_))
-                | Progress::Terminated(false)
-        )
+        matches!(self, Progress::Terminated(false))
     }
 }
 
@@ -53,7 +162,10 @@ use crate::ci::job::container_configuration::ContainerConfiguration;
 use crate::ci::job::container_configuration::ContainerConfiguration::Container;
 use crate::ci::job::inspection::JobProgress;
 use ports::CommandRunner;
-use std::collections::HashMap;
+use secrets::Secrets;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
 
 #[derive(Eq, PartialEq, Hash, Debug, Clone)]
 pub struct Job {
@@ -62,6 +174,17 @@ pub struct Job {
     container: ContainerConfiguration,
     instructions: Vec<String>,
     skip_if: Option<String>,
+    timeout: Option<Duration>,
+    allow_failure: bool,
+    retry: Retry,
+    before_script: HookScript,
+    /// Runs whatever the result of the instructions
+    after_script: HookScript,
+    /// Environment of the instructions, on top of the one of qad
+    env: BTreeMap<String, String>,
+    /// Hidden from everything the job reports
+    secrets: Secrets,
+    up_to_date: bool,
 }
 
 impl Job {
@@ -69,47 +192,193 @@ impl Job {
         &self.name
     }
 
-    pub fn forward_env(&mut self, env: &HashMap<String, Vec<String>>) {
+    pub const fn container(&self) -> &ContainerConfiguration {
+        &self.container
+    }
+
+    pub const fn env(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    /// The instructions run with `env`, which is forwarded to the container as well
+    pub fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
         if let Container(container) = &mut self.container {
             for key in env.keys() {
                 container.forward_env(key);
             }
         }
+        self.env = env;
+        self
+    }
+
+    pub const fn secrets(&self) -> &Secrets {
+        &self.secrets
+    }
+
+    pub fn with_secrets(mut self, secrets: Secrets) -> Self {
+        self.secrets = secrets;
+        self
     }
 
     pub fn group(&self) -> Option<&str> {
         self.group.as_deref()
     }
 
+    /// Every instruction of the job is killed once it runs longer than `timeout`
+    pub const fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// A failure of the job does not cancel the jobs coming after it
+    pub const fn allowing_failure(mut self, allow_failure: bool) -> Self {
+        self.allow_failure = allow_failure;
+        self
+    }
+
+    pub const fn allows_failure(&self) -> bool {
+        self.allow_failure
+    }
+
+    /// The failing instructions of the job run again, as `retry` allows
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_hooks(mut self, before_script: HookScript, after_script: HookScript) -> Self {
+        self.before_script = before_script;
+        self.after_script = after_script;
+        self
+    }
+
+    /// The job is skipped, as its inputs did not change since its last success
+    pub const fn mark_up_to_date(mut self) -> Self {
+        self.up_to_date = true;
+        self
+    }
+
+    fn report(&self, consumer: &dyn ProgressConsumer, progress: Progress) {
+        consumer.consume(JobProgress::new(
+            &self.name,
+            self.secrets.mask_progress(progress),
+        ));
+    }
+
     pub fn start(&self, runner: &impl CommandRunner, consumer: &dyn ProgressConsumer) {
+        if self.up_to_date {
+            self.report(
+                consumer,
+                Progress::Skipped("inputs unchanged since the last success".to_string()),
+            );
+            self.report(consumer, Progress::Terminated(true));
+            return;
+        }
+
         if let Some(condition) = &self.skip_if {
-            if runner.run(condition).succeeded() {
-                consumer.consume(JobProgress::new(&self.name, Progress::Skipped));
-                consumer.consume(JobProgress::new(&self.name, Progress::Terminated(true)));
+            if runner
+                .run_streaming(condition, &self.env, None, &|_| {})
+                .succeeded()
+            {
+                self.report(
+                    consumer,
+                    Progress::Skipped("skip_if condition succeeded".to_string()),
+                );
+                self.report(consumer, Progress::Terminated(true));
                 return;
             }
         }
 
-        let mut success = true;
-        for instruction in &self.instructions {
-            consumer.consume(JobProgress::new(
-                &self.name,
-                Progress::Started(instruction.clone()),
-            ));
+        let success = self.start_container(runner, consumer) && self.run_script(runner, consumer);
+        if let Some(command) = self.container.stop(&self.name) {
+            runner.run_cleanup(&command, &self.env, None);
+        }
 
-            let command = self.container.compile(instruction);
+        self.report(consumer, Progress::Terminated(success));
+    }
 
-            let output = runner.run(&command);
+    /// Starts the container of the job, when it lives as long as the job. Whether it did
+    fn start_container(
+        &self,
+        runner: &impl CommandRunner,
+        consumer: &dyn ProgressConsumer,
+    ) -> bool {
+        let Some(command) = self.container.start(&self.name) else {
+            return true;
+        };
+        let output = runner.run_streaming(&command, &self.env, self.timeout, &|_| {});
+        let success = output.succeeded();
+        self.report(consumer, Progress::ContainerStart(output));
+        success
+    }
 
-            success = output.succeeded();
-            let partial = Progress::Partial(instruction.clone(), output);
-            consumer.consume(JobProgress::new(&self.name, partial));
-            if !success {
-                break;
+    /// Runs the hooks and the instructions of the job. Whether they succeeded
+    fn run_script(&self, runner: &impl CommandRunner, consumer: &dyn ProgressConsumer) -> bool {
+        let mut success = self.hook(Hook::BeforeScript, &self.before_script, runner, consumer);
+        if success {
+            for instruction in &self.instructions {
+                success = self.run(instruction, runner, consumer);
+                if !success {
+                    break;
+                }
             }
         }
+        success & self.hook(Hook::AfterScript, &self.after_script, runner, consumer)
+    }
+
+    /// Runs a hook of the job, in its container. Whether the job may go on
+    fn hook(
+        &self,
+        hook: Hook,
+        script: &HookScript,
+        runner: &impl CommandRunner,
+        consumer: &dyn ProgressConsumer,
+    ) -> bool {
+        script.run(
+            |instruction| {
+                let command = self.container.compile(&self.name, instruction);
+                if hook == Hook::AfterScript {
+                    runner.run_cleanup(&command, &self.env, self.timeout)
+                } else {
+                    runner.run_streaming(&command, &self.env, self.timeout, &|_| {})
+                }
+            },
+            |instruction, output| {
+                self.report(
+                    consumer,
+                    Progress::Hook(hook, instruction.to_string(), output),
+                );
+            },
+        )
+    }
 
-        consumer.consume(JobProgress::new(&self.name, Progress::Terminated(success)));
+    /// Runs an instruction until it succeeds, or fails in a way or a number of times the
+    /// retry of the job does not cover
+    fn run(
+        &self,
+        instruction: &str,
+        runner: &impl CommandRunner,
+        consumer: &dyn ProgressConsumer,
+    ) -> bool {
+        let command = self.container.compile(&self.name, instruction);
+        let mut attempt = 1;
+        loop {
+            self.report(consumer, Progress::Started(instruction.to_string()));
+
+            let output = runner.run_streaming(&command, &self.env, self.timeout, &|line| {
+                self.report(consumer, Progress::Line(line));
+            });
+
+            let success = output.succeeded();
+            let retried =
+                attempt <= self.retry.max && self.retry.covers(&output) && !runner.cancelled();
+            self.report(consumer, Progress::Partial(instruction.to_string(), output));
+            if !retried {
+                return success;
+            }
+            attempt += 1;
+            self.report(consumer, Progress::Retrying(attempt, self.retry.max + 1));
+        }
     }
 
     pub const fn long(
@@ -124,6 +393,14 @@ impl Job {
             container: ContainerConfiguration::None,
             instructions,
             skip_if,
+            timeout: None,
+            allow_failure: false,
+            retry: Retry::none(),
+            before_script: HookScript::none(),
+            after_script: HookScript::none(),
+            env: BTreeMap::new(),
+            secrets: Secrets::none(),
+            up_to_date: false,
         }
     }
 
@@ -140,6 +417,14 @@ impl Job {
             container,
             instructions,
             skip_if,
+            timeout: None,
+            allow_failure: false,
+            retry: Retry::none(),
+            before_script: HookScript::none(),
+            after_script: HookScript::none(),
+            env: BTreeMap::new(),
+            secrets: Secrets::none(),
+            up_to_date: false,
         }
     }
 }
@@ -1,17 +1,65 @@
 use crate::ci::job::inspection::{JobProgress, JobProgressTracker};
 use crate::ci::job::Job;
 use crate::ci::job::Output;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::sync::mpsc::Sender;
+use std::time::Duration;
 
 pub trait CommandRunner {
     fn run(&self, args: &str) -> Output;
+
+    /// Runs `args` with `env` added to its environment, killing it after `timeout`,
+    /// and calls `on_line` for every line it outputs while it runs
+    fn run_streaming(
+        &self,
+        args: &str,
+        _env: &BTreeMap<String, String>,
+        _timeout: Option<Duration>,
+        _on_line: &dyn Fn(String),
+    ) -> Output {
+        self.run(args)
+    }
+
+    /// Whether the runner was cancelled, so that failing instructions are not run again
+    fn cancelled(&self) -> bool {
+        false
+    }
+
+    /// Runs `args` like `run_streaming`, even once the runner is cancelled,
+    /// so that cleanups such as `after_script` and `teardown` run anyway
+    fn run_cleanup(
+        &self,
+        args: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+    ) -> Output {
+        self.run_streaming(args, env, timeout, &|_| {})
+    }
 }
 
 pub trait SystemFacade: CommandRunner {
     fn consume_job(&mut self, jobs: Job, tx: Sender<JobProgress>);
     fn delay(&mut self) -> usize;
-    fn write_env(&self, env: HashMap<String, Vec<String>>);
+    /// Whether the user asked to stop the ci (SIGINT, SIGTERM)
+    fn interrupted(&self) -> bool;
+    fn cancel_running_jobs(&mut self);
+
+    /// Jobs whose run became outdated, such as the ones watching changed files.
+    /// The running ones are cancelled
+    fn outdated_jobs(&mut self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Kills the running instruction of `job` and refuses to start its next ones
+    fn cancel_job(&mut self, _job: &str) {}
+
+    /// Whether `job` last succeeded with the same `fingerprint`
+    fn is_cached(&self, _job: &str, _fingerprint: &str) -> bool {
+        false
+    }
+
+    /// Remembers that `job` succeeded with `fingerprint`
+    fn cache(&mut self, _job: &str, _fingerprint: &str) {}
 }
 
 pub trait FinalCiDisplay {
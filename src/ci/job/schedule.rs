@@ -1,11 +1,19 @@
+use crate::ci::cache::fingerprint;
 use crate::ci::config::{CliOption, Config, JobDesc};
 use crate::ci::job::dag::{Dag, JobResult, JobState};
+use crate::ci::job::env_parser::parse_dotenv;
 use crate::ci::job::inspection::JobProgress;
 use crate::ci::job::ports::{SystemFacade, UserFacade};
+use crate::ci::job::secrets::{Secrets, MIN_LENGTH};
 use crate::ci::job::shell_interpreter::ShellInterpreter;
-use crate::ci::job::Job;
+use crate::ci::job::{Hook, HookScript, Job};
 use crate::ci::job::{JobProgressTracker, Progress};
+use anyhow::anyhow;
+use glob::Pattern;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
 use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::time::Duration;
 
 fn job_group_filter(job: &JobDesc, groups: &Vec<String>) -> bool {
     if groups.is_empty() {
@@ -21,93 +29,393 @@ fn job_group_filter(job: &JobDesc, groups: &Vec<String>) -> bool {
     false
 }
 
-pub fn schedule(
-    cli_option: &CliOption,
-    ci_config: Config,
-    system_facade: &mut dyn SystemFacade,
-    user_facade: &mut dyn UserFacade,
-    envtext: Option<String>,
-) -> anyhow::Result<JobProgressTracker> {
-    let env = {
-        let parser = ShellInterpreter::new(user_facade, system_facade);
-        parser.interpret(envtext)?
-    };
-
-    let jobs = if cli_option.job.is_none() {
-        ci_config
+pub fn select_jobs(cli_option: &CliOption, ci_config: &Config) -> anyhow::Result<Vec<Job>> {
+    if cli_option.only.is_empty() {
+        return Ok(ci_config
             .jobs
             .iter()
             .filter(|desc: &&JobDesc| job_group_filter(desc, &ci_config.groups))
             .cloned()
             .map(Into::into)
-            .collect::<Vec<Job>>()
-    } else {
-        let filter = cli_option.job.as_ref().unwrap();
-        if let Some(group) = filter.strip_prefix("group:") {
+            .collect::<Vec<Job>>());
+    }
+
+    let mut selected: Vec<String> = vec![];
+    for filter in &cli_option.only {
+        let matching: Vec<&JobDesc> = if let Some(group) = filter.strip_prefix("group:") {
             ci_config
                 .jobs
                 .iter()
                 .filter(|job| !job.group.is_empty() && group == job.group[0])
-                .cloned()
-                .map(Into::into)
-                .collect::<Vec<Job>>()
+                .collect()
         } else {
+            let names = ci_config.expand(filter);
             ci_config
                 .jobs
                 .iter()
-                .filter(|job| filter == &job.name)
-                .cloned()
-                .map(Into::into)
-                .collect::<Vec<Job>>()
+                .filter(|job| names.contains(&job.name))
+                .collect()
+        };
+        let known_group = filter
+            .strip_prefix("group:")
+            .is_some_and(|group| ci_config.groups.iter().any(|known| known == group));
+        if matching.is_empty() && !known_group {
+            return Err(anyhow!("no job or group matches {filter}"));
+        }
+        for job in matching {
+            if !selected.contains(&job.name) {
+                selected.push(job.name.clone());
+            }
+        }
+    }
+
+    if cli_option.with_deps {
+        let all_jobs: Vec<Job> = ci_config.jobs.iter().cloned().map(Into::into).collect();
+        selected.extend(Dag::prerequisites(
+            &all_jobs,
+            &ci_config.constraints,
+            &ci_config.groups,
+            &selected,
+        )?);
+    }
+
+    Ok(ci_config
+        .jobs
+        .iter()
+        .filter(|job| selected.contains(&job.name))
+        .cloned()
+        .map(Into::into)
+        .collect::<Vec<Job>>())
+}
+
+/// Whether `text` is `pattern`, or matches it with the glob syntax of the `inputs` and `watch` lists.
+/// Names of matrix jobs hold brackets, which start a character class in a glob
+fn glob_match(pattern: &str, text: &str) -> bool {
+    pattern == text || Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(text))
+}
+
+fn is_skipped(patterns: &[String], job: &Job) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_prefix("group:") {
+            Some(group) => job.group().is_some_and(|name| glob_match(group, name)),
+            None => glob_match(pattern, job.name()),
+        })
+}
+
+/// Removes the jobs matching `--skip` from the selection, and returns their names.
+/// A kept job may not need a skipped one
+fn skip_jobs(
+    cli_option: &CliOption,
+    ci_config: &Config,
+    jobs: Vec<Job>,
+) -> anyhow::Result<(Vec<Job>, Vec<String>)> {
+    let all_jobs: Vec<Job> = ci_config.jobs.iter().cloned().map(Into::into).collect();
+    for pattern in &cli_option.skip {
+        if !all_jobs
+            .iter()
+            .any(|job| is_skipped(&ci_config.expand(pattern), job))
+        {
+            return Err(anyhow!("no job or group matches {pattern}"));
+        }
+    }
+
+    // a matrix job stands for all its cells
+    let patterns: Vec<String> = cli_option
+        .skip
+        .iter()
+        .flat_map(|pattern| ci_config.expand(pattern))
+        .collect();
+    let (skipped, jobs): (Vec<Job>, Vec<Job>) =
+        jobs.into_iter().partition(|job| is_skipped(&patterns, job));
+    let skipped: Vec<String> = skipped.iter().map(|job| job.name().to_string()).collect();
+
+    for (blocker, blocked) in &ci_config.constraints {
+        if skipped.contains(blocker) && jobs.iter().any(|job| job.name() == blocked) {
+            return Err(anyhow!(
+                "cannot skip {blocker}: {blocked} needs it (skip {blocked} as well)"
+            ));
+        }
+    }
+
+    Ok((jobs, skipped))
+}
+
+/// The env section, as given to the instructions
+fn global_env(env: &HashMap<String, Vec<String>>) -> BTreeMap<String, String> {
+    env.iter()
+        .map(|(key, values)| (key.clone(), values.join("\n")))
+        .collect()
+}
+
+/// Gives each job the global env, then the content of its `env_file`, then its `variables`,
+/// and the values of the secret keys among them
+fn job_env(
+    ci_config: &Config,
+    jobs: Vec<Job>,
+    env: &HashMap<String, Vec<String>>,
+) -> anyhow::Result<Vec<Job>> {
+    let jobs = jobs
+        .into_iter()
+        .map(|job| {
+            let mut job_env = global_env(env);
+            if let Some(desc) = ci_config.jobs.iter().find(|desc| desc.name == job.name()) {
+                if let Some(path) = &desc.env_file {
+                    let text = fs::read_to_string(path).map_err(|error| {
+                        anyhow!("could not read env_file {path} of {}: {error}", desc.name)
+                    })?;
+                    job_env.extend(parse_dotenv(&text).map_err(|error| {
+                        anyhow!("invalid env_file {path} of {}: {error}", desc.name)
+                    })?);
+                }
+                job_env.extend(desc.variables.iter().cloned());
+            }
+            let secrets = Secrets::new(&ci_config.secrets, &job_env);
+            Ok(job.with_env(job_env).with_secrets(secrets))
+        })
+        .collect::<anyhow::Result<Vec<Job>>>()?;
+
+    let unmasked: BTreeSet<String> = jobs
+        .iter()
+        .flat_map(|job| Secrets::unmasked(&ci_config.secrets, job.env()))
+        .collect();
+    for key in unmasked {
+        eprintln!(
+            "{}: secret {key} is shorter than {MIN_LENGTH} characters, it is not masked",
+            crate::PACKAGE_NAME
+        );
+    }
+    Ok(jobs)
+}
+
+/// Fingerprints the selected jobs which have inputs,
+/// and marks up to date the ones which already succeeded with the same fingerprint
+fn check_cache(
+    cli_option: &CliOption,
+    ci_config: &Config,
+    jobs: Vec<Job>,
+    system_facade: &dyn SystemFacade,
+) -> anyhow::Result<(Vec<Job>, HashMap<String, String>)> {
+    let mut fingerprints = HashMap::new();
+    let jobs = jobs
+        .into_iter()
+        .map(|job| {
+            let Some(desc) = ci_config
+                .jobs
+                .iter()
+                .find(|desc| desc.name == job.name() && !desc.inputs.is_empty())
+            else {
+                return Ok(job);
+            };
+            let fingerprint = fingerprint(desc, job.env())?;
+            let up_to_date =
+                !cli_option.no_cache && system_facade.is_cached(job.name(), &fingerprint);
+            fingerprints.insert(job.name().to_string(), fingerprint);
+            Ok(if up_to_date {
+                job.mark_up_to_date()
+            } else {
+                job
+            })
+        })
+        .collect::<anyhow::Result<Vec<Job>>>()?;
+    Ok((jobs, fingerprints))
+}
+
+/// Constraints hold between the selected jobs only
+fn select_constraints(
+    cli_option: &CliOption,
+    constraints: Vec<(String, String)>,
+    jobs: &[Job],
+) -> Vec<(String, String)> {
+    if cli_option.only.is_empty() && cli_option.skip.is_empty() {
+        return constraints;
+    }
+    let selected = |name: &String| jobs.iter().any(|job| job.name() == name);
+    constraints
+        .into_iter()
+        .filter(|(blocker, blocked)| selected(blocker) && selected(blocked))
+        .collect()
+}
+
+/// The setup and the teardown of the ci, which run with the global env
+struct CiHooks {
+    setup: HookScript,
+    teardown: HookScript,
+    env: BTreeMap<String, String>,
+    secrets: Secrets,
+    timeout: Option<Duration>,
+}
+
+impl CiHooks {
+    /// Whether the ci may go on, a fatal failure fails it
+    fn run(
+        &self,
+        hook: Hook,
+        script: &HookScript,
+        system_facade: &dyn SystemFacade,
+        tracker: &mut JobProgressTracker,
+    ) -> bool {
+        let success = script.run(
+            |instruction| {
+                if hook == Hook::Teardown {
+                    system_facade.run_cleanup(instruction, &self.env, self.timeout)
+                } else {
+                    system_facade.run_streaming(instruction, &self.env, self.timeout, &|_| {})
+                }
+            },
+            |instruction, output| {
+                tracker.record_hook(
+                    hook,
+                    &self.secrets.mask(instruction),
+                    self.secrets.mask_output(output),
+                );
+            },
+        );
+        if !success {
+            tracker.has_failed = true;
         }
+        success
+    }
+}
+
+pub fn schedule(
+    cli_option: &CliOption,
+    mut ci_config: Config,
+    system_facade: &mut dyn SystemFacade,
+    user_facade: &mut dyn UserFacade,
+    envtext: Option<String>,
+) -> anyhow::Result<JobProgressTracker> {
+    let env = {
+        let parser = ShellInterpreter::new(user_facade, system_facade);
+        parser.interpret(envtext)?
     };
 
-    let constraints = if cli_option.job.is_none() {
-        ci_config.constraints
-    } else {
-        vec![]
+    let concurrency = cli_option.jobs.or(ci_config.concurrency);
+    if concurrency == Some(0) {
+        return Err(anyhow!("at least one job must be allowed to run at once"));
+    }
+
+    for job in &mut ci_config.jobs {
+        job.timeout = job.timeout.or(ci_config.timeout);
+    }
+
+    let jobs = select_jobs(cli_option, &ci_config)?;
+    let (jobs, skipped) = skip_jobs(cli_option, &ci_config, jobs)?;
+    let jobs = job_env(&ci_config, jobs, &env)?;
+    let (jobs, fingerprints) = check_cache(cli_option, &ci_config, jobs, system_facade)?;
+
+    let hooks = CiHooks {
+        setup: ci_config.setup,
+        teardown: ci_config.teardown,
+        secrets: Secrets::new(&ci_config.secrets, &global_env(&env)),
+        env: global_env(&env),
+        timeout: ci_config.timeout,
     };
+    let constraints = select_constraints(cli_option, ci_config.constraints, &jobs);
+    let allowed_to_fail: Vec<String> = jobs
+        .iter()
+        .filter(|job| job.allows_failure())
+        .map(|job| job.name().to_string())
+        .collect();
 
-    let mut jobs = Dag::new(&jobs, &constraints, &ci_config.groups, &env)?;
+    let mut jobs = Dag::new(&jobs, &constraints, &ci_config.groups)
+        .map_err(|error| error.locate(&ci_config.origins))?
+        .keep_going(cli_option.keep_going || ci_config.keep_going);
 
     let mut tracker = JobProgressTracker::new();
 
+    for name in &skipped {
+        tracker.record(JobProgress::new(
+            name,
+            Progress::Skipped("left out with --skip".to_string()),
+        ));
+        tracker.record(JobProgress::new(name, Progress::Terminated(true)));
+    }
+
     if jobs.is_finished() {
         tracker.finish();
         return Ok(tracker);
     }
 
-    system_facade.write_env(env);
+    record_initial_states(&jobs, &allowed_to_fail, &mut tracker);
 
-    for job in jobs.enumerate() {
-        tracker.record(JobProgress::new(
-            &job.name,
-            match job.state {
-                JobState::Pending => Progress::Available,
-                JobState::Blocked => Progress::Blocked(job.block.clone()),
-                _ => {
-                    unreachable!("This state is impossible with no poll yet")
-                }
-            },
-        ));
+    user_facade.set_up(&tracker);
+
+    if hooks.run(Hook::Setup, &hooks.setup, system_facade, &mut tracker) {
+        run_jobs(
+            &mut jobs,
+            &mut tracker,
+            concurrency,
+            &fingerprints,
+            system_facade,
+            user_facade,
+        );
+    } else {
+        jobs.cancel_pending();
+        record_cancellations(&jobs, &mut tracker);
     }
+    hooks.run(Hook::Teardown, &hooks.teardown, system_facade, &mut tracker);
+    tracker.finish();
 
-    let (tx, rx) = channel();
+    user_facade.tear_down(&tracker);
 
-    user_facade.set_up(&tracker);
+    Ok(tracker)
+}
+
+/// Runs the jobs as their constraints allow, until all of them are resolved
+fn run_jobs(
+    jobs: &mut Dag,
+    tracker: &mut JobProgressTracker,
+    concurrency: Option<usize>,
+    fingerprints: &HashMap<String, String>,
+    system_facade: &mut dyn SystemFacade,
+    user_facade: &mut dyn UserFacade,
+) {
+    let (tx, rx) = channel();
 
     let mut delay: usize = 0;
+    let mut running: usize = 0;
+    let mut outdated: Vec<String> = vec![];
     loop {
-        while let Some(job) = jobs.poll() {
+        if !tracker.interrupted && system_facade.interrupted() {
+            system_facade.cancel_running_jobs();
+            jobs.cancel_pending();
+            tracker.interrupt();
+            record_cancellations(jobs, tracker);
+        }
+        cancel_outdated_jobs(jobs, system_facade, &mut outdated);
+
+        while concurrency.is_none_or(|limit| running < limit) {
+            let Some(job) = jobs.poll() else {
+                break;
+            };
+            running += 1;
+            tracker.start(job.name());
             system_facade.consume_job(job.clone(), tx.clone());
         }
 
+        for job in jobs.enumerate() {
+            if matches!(job.state, JobState::Pending)
+                && !matches!(tracker.states[&job.name].last(), Progress::Queued)
+            {
+                tracker.record(JobProgress::new(&job.name, Progress::Queued));
+            }
+        }
+
         while let Some(progress) = read(&rx) {
-            let mut cancel_list: Vec<String> = vec![];
-            let name = progress.name();
-            if let Progress::Terminated(success) = progress.1 {
+            let name = progress.name().to_string();
+            let terminated = match progress.1 {
+                Progress::Terminated(success) => Some(success),
+                _ => None,
+            };
+            tracker.record(progress);
+            if let Some(success) = terminated {
+                running -= 1;
+                if let Some(fingerprint) = fingerprints.get(&name).filter(|_| success) {
+                    system_facade.cache(&name, fingerprint);
+                }
                 jobs.record_event(
-                    name,
+                    &name,
                     if success {
                         JobResult::Success
                     } else {
@@ -115,31 +423,66 @@ pub fn schedule(
                     },
                 );
                 if !success {
-                    cancel_list = jobs
-                        .enumerate()
-                        .iter()
-                        .filter(|job| matches!(job.state, JobState::Cancelled(_)))
-                        .map(|job| job.name.clone())
-                        .collect();
+                    if tracker.interrupted || outdated.contains(&name) {
+                        tracker.record(JobProgress::cancel(name));
+                    }
+                    record_cancellations(jobs, tracker);
                 }
             }
-            tracker.record(progress);
-            for cancel in cancel_list {
-                tracker.record(JobProgress::cancel(cancel));
-            }
         }
 
         if jobs.is_finished() {
-            tracker.finish();
             break;
         }
-        user_facade.run(&tracker, delay);
+        user_facade.run(tracker, delay);
         delay = system_facade.delay();
     }
+}
 
-    user_facade.tear_down(&tracker);
+/// Cancels the running jobs the system facade reports as outdated, adding them to `outdated`
+fn cancel_outdated_jobs(
+    jobs: &Dag,
+    system_facade: &mut dyn SystemFacade,
+    outdated: &mut Vec<String>,
+) {
+    for name in system_facade.outdated_jobs() {
+        let running = jobs
+            .enumerate()
+            .iter()
+            .any(|job| job.name == name && matches!(job.state, JobState::Started));
+        if running && !outdated.contains(&name) {
+            system_facade.cancel_job(&name);
+            outdated.push(name);
+        }
+    }
+}
 
-    Ok(tracker)
+fn record_initial_states(jobs: &Dag, allowed_to_fail: &[String], tracker: &mut JobProgressTracker) {
+    for job in jobs.enumerate() {
+        tracker.record(JobProgress::new(
+            &job.name,
+            match job.state {
+                JobState::Pending => Progress::Available,
+                JobState::Blocked => Progress::Blocked(job.block.clone()),
+                _ => {
+                    unreachable!("This state is impossible with no poll yet")
+                }
+            },
+        ));
+    }
+    for name in allowed_to_fail {
+        tracker.allow_failure(name);
+    }
+}
+
+fn record_cancellations(jobs: &Dag, tracker: &mut JobProgressTracker) {
+    for job in jobs.enumerate() {
+        if matches!(job.state, JobState::Cancelled(_))
+            && !matches!(tracker.states[&job.name].last(), Progress::Cancelled)
+        {
+            tracker.record(JobProgress::cancel(job.name));
+        }
+    }
 }
 
 pub fn read(rx: &Receiver<JobProgress>) -> Option<JobProgress> {
@@ -155,45 +498,524 @@ pub fn read(rx: &Receiver<JobProgress>) -> Option<JobProgress> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ci::job::ports::CommandRunner;
-    use crate::ci::job::Output;
-    use std::collections::HashMap;
-    use std::sync::mpsc::Sender;
+    use crate::ci::display::silent::Display as SilentDisplay;
+    use crate::ci::job::tests::{job_desc, run_ci, temp_path, TestJobStarter};
+    use crate::ci::job::{Failure, Hook, HookScript, Output, Retry};
+    use crate::strvec;
 
-    pub struct TestJobStarter {}
+    #[test]
+    pub fn job_env_overrides_global_env() {
+        let env_file = temp_path("env");
+        fs::write(&env_file, "FROM_FILE=file\nSHARED=file\n").unwrap();
+        let config = Config {
+            jobs: vec![JobDesc {
+                env_file: Some(env_file.display().to_string()),
+                variables: vec![("SHARED".to_string(), "job".to_string())],
+                ..job_desc("a")
+            }],
+            ..Default::default()
+        };
+        let global = HashMap::from([
+            ("GLOBAL".to_string(), strvec!("two", "lines")),
+            ("SHARED".to_string(), strvec!("global")),
+        ]);
 
-    impl CommandRunner for TestJobStarter {
-        fn run(&self, _: &str) -> Output {
-            Output::Success(String::new(), String::new())
-        }
+        let jobs = job_env(
+            &config,
+            select_jobs(&CliOption::default(), &config).unwrap(),
+            &global,
+        );
+        fs::remove_file(&env_file).unwrap();
+
+        assert_eq!(
+            &BTreeMap::from([
+                ("FROM_FILE".to_string(), "file".to_string()),
+                ("GLOBAL".to_string(), "two\nlines".to_string()),
+                ("SHARED".to_string(), "job".to_string()),
+            ]),
+            jobs.unwrap()[0].env()
+        );
     }
 
-    impl SystemFacade for TestJobStarter {
-        fn consume_job(&mut self, job: Job, tx: Sender<JobProgress>) {
-            job.start(&TestJobRunner {}, &tx);
-        }
+    #[test]
+    pub fn secrets_are_masked() {
+        let config = Config {
+            jobs: vec![JobDesc {
+                script: strvec!("ok:token s3cr3t"),
+                variables: vec![("TOKEN".to_string(), "s3cr3t".to_string())],
+                ..job_desc("a")
+            }],
+            secrets: strvec!("TOKEN"),
+            ..Default::default()
+        };
 
-        fn delay(&mut self) -> usize {
-            0
-        }
+        let tracker = run_ci(&CliOption::default(), config).unwrap();
 
-        fn write_env(&self, _: HashMap<String, Vec<String>>) {}
+        assert!(tracker.states["a"].progresses.contains(&Progress::Partial(
+            "ok:token ****".to_string(),
+            Output::Success("token ****".to_string(), String::new())
+        )));
     }
 
-    pub struct TestJobRunner {}
-    impl CommandRunner for TestJobRunner {
-        fn run(&self, job: &str) -> Output {
-            // clippy::option_if_let_else makes a bad suggestion
-            // https://github.com/rust-lang/rust-clippy/issues/8829
-            if let Some(stripped) = job.strip_prefix("ok:") {
-                Output::Success(stripped.to_string(), String::new())
-            } else if let Some(stripped) = job.strip_prefix("ko:") {
-                Output::JobError(stripped.to_string(), String::new())
-            } else if let Some(stripped) = job.strip_prefix("crash:") {
-                Output::ProcessError(stripped.to_string())
-            } else {
-                panic!("Job should begin with ok:, ko, or crash: (actual: '{job}')",)
-            }
+    #[test]
+    pub fn keep_going_runs_the_next_groups() {
+        let config = Config {
+            jobs: vec![
+                JobDesc {
+                    script: strvec!("ko:badly formatted"),
+                    group: strvec!("check"),
+                    ..job_desc("fmt")
+                },
+                JobDesc {
+                    group: strvec!("test"),
+                    ..job_desc("tests")
+                },
+                JobDesc {
+                    group: strvec!("test"),
+                    ..job_desc("deploy")
+                },
+            ],
+            groups: strvec!("check", "test"),
+            constraints: vec![("fmt".to_string(), "deploy".to_string())],
+            keep_going: true,
+            ..Default::default()
+        };
+
+        let tracker = run_ci(&CliOption::default(), config).unwrap();
+
+        assert!(tracker.has_failed);
+        assert_eq!(Some(false), tracker.states["fmt"].terminated());
+        assert_eq!(Some(true), tracker.states["tests"].terminated());
+        assert_eq!(&Progress::Cancelled, tracker.states["deploy"].last());
+    }
+
+    #[test]
+    pub fn allowed_failures_do_not_fail_the_ci() {
+        let config = Config {
+            jobs: vec![
+                JobDesc {
+                    script: strvec!("ko:vulnerable dependency"),
+                    allow_failure: true,
+                    ..job_desc("audit")
+                },
+                job_desc("deploy"),
+            ],
+            constraints: vec![("audit".to_string(), "deploy".to_string())],
+            ..Default::default()
+        };
+
+        let tracker = run_ci(&CliOption::default(), config).unwrap();
+
+        assert!(!tracker.has_failed);
+        assert!(tracker.has_allowed_failures);
+        assert!(tracker.states["audit"].failure_allowed());
+        assert_eq!(Some(true), tracker.states["deploy"].terminated());
+    }
+
+    #[test]
+    pub fn failing_instructions_are_retried() {
+        let retry = |max: u32, when: Vec<Failure>| Retry { max, when };
+        let config = Config {
+            jobs: vec![
+                JobDesc {
+                    script: strvec!("flaky:2:passed"),
+                    retry: retry(2, vec![Failure::JobError]),
+                    ..job_desc("tests")
+                },
+                JobDesc {
+                    script: strvec!("flaky:2:passed"),
+                    retry: retry(1, vec![Failure::JobError]),
+                    ..job_desc("lint")
+                },
+                JobDesc {
+                    script: strvec!("crash:no shell"),
+                    retry: retry(2, vec![Failure::JobError]),
+                    ..job_desc("build")
+                },
+            ],
+            ..Default::default()
+        };
+
+        let tracker = run_ci(&CliOption::default(), config).unwrap();
+
+        let attempts = |job: &str| {
+            tracker.states[job]
+                .progresses
+                .iter()
+                .filter(|progress| matches!(progress, Progress::Partial(_, _)))
+                .count()
+        };
+        assert_eq!(Some(true), tracker.states["tests"].terminated());
+        assert_eq!(3, attempts("tests"));
+        assert!(tracker.states["tests"]
+            .progresses
+            .contains(&Progress::Retrying(3, 3)));
+        assert_eq!(Some(false), tracker.states["lint"].terminated());
+        assert_eq!(2, attempts("lint"));
+        assert_eq!(Some(false), tracker.states["build"].terminated());
+        assert_eq!(1, attempts("build"));
+    }
+
+    #[test]
+    pub fn hooks_run_around_the_jobs_and_the_ci() {
+        let hook = |instructions: Vec<String>, fatal: bool| HookScript {
+            instructions,
+            fatal,
+        };
+        let config = Config {
+            jobs: vec![
+                JobDesc {
+                    script: strvec!("ok:tested"),
+                    before_script: hook(strvec!("ko:no database"), true),
+                    after_script: hook(strvec!("ok:cleaned"), false),
+                    ..job_desc("tests")
+                },
+                JobDesc {
+                    script: strvec!("ko:bad style"),
+                    after_script: hook(strvec!("ok:cleaned"), false),
+                    ..job_desc("lint")
+                },
+                JobDesc {
+                    after_script: hook(strvec!("ko:nothing to clean"), false),
+                    ..job_desc("docs")
+                },
+            ],
+            setup: hook(strvec!("ok:up"), true),
+            teardown: hook(strvec!("ko:down"), false),
+            ..Default::default()
+        };
+
+        let tracker = run_ci(&CliOption::default(), config).unwrap();
+
+        let hooks = |job: &str| {
+            tracker.states[job]
+                .progresses
+                .iter()
+                .filter_map(|progress| match progress {
+                    Progress::Hook(hook, _, output) => Some((*hook, output.succeeded())),
+                    _ => None,
+                })
+                .collect::<Vec<(Hook, bool)>>()
+        };
+        let instructions = |job: &str| tracker.states[job].instruction_list().len();
+        assert_eq!(
+            vec![(Hook::BeforeScript, false), (Hook::AfterScript, true)],
+            hooks("tests")
+        );
+        assert_eq!(0, instructions("tests"));
+        // the job ran its before_script, which counts in its duration
+        assert!(tracker.states["tests"].duration().is_some());
+        assert_eq!(Some(false), tracker.states["tests"].terminated());
+        assert_eq!(vec![(Hook::AfterScript, true)], hooks("lint"));
+        assert_eq!(1, instructions("lint"));
+        assert_eq!(Some(true), tracker.states["docs"].terminated());
+        assert_eq!(2, tracker.hooks.progresses.len());
+    }
+
+    #[test]
+    pub fn failed_setup_cancels_the_jobs() {
+        let config = Config {
+            jobs: vec![job_desc("tests")],
+            setup: HookScript {
+                instructions: strvec!("ko:no docker"),
+                fatal: true,
+            },
+            teardown: HookScript {
+                instructions: strvec!("ok:down"),
+                fatal: false,
+            },
+            ..Default::default()
+        };
+
+        let tracker = run_ci(&CliOption::default(), config).unwrap();
+
+        assert!(tracker.has_failed);
+        assert_eq!(&Progress::Cancelled, tracker.states["tests"].last());
+        assert!(matches!(
+            tracker.hooks.last(),
+            Progress::Hook(Hook::Teardown, _, Output::Success(_, _))
+        ));
+    }
+
+    #[test]
+    pub fn concurrency_queues_jobs() {
+        let config = Config {
+            jobs: vec![job_desc("a"), job_desc("b")],
+            ..Default::default()
+        };
+        let cli_option = CliOption {
+            jobs: Some(1),
+            ..Default::default()
+        };
+
+        let tracker = run_ci(&cli_option, config).unwrap();
+
+        assert!(!tracker.has_failed);
+        assert!(!tracker.states["a"].progresses.contains(&Progress::Queued));
+        assert!(tracker.states["b"].progresses.contains(&Progress::Queued));
+        assert_eq!(Some(true), tracker.states["b"].terminated());
+    }
+
+    #[test]
+    pub fn invalid_options_are_rejected() {
+        let config = Config {
+            jobs: vec![
+                JobDesc {
+                    group: strvec!("e2e"),
+                    ..job_desc("a")
+                },
+                job_desc("c"),
+            ],
+            constraints: vec![("a".to_string(), "c".to_string())],
+            ..Default::default()
+        };
+        let cases = [
+            (
+                "no job may run",
+                CliOption {
+                    jobs: Some(0),
+                    ..Default::default()
+                },
+            ),
+            (
+                "unknown group",
+                CliOption {
+                    only: strvec!("a", "group:nope"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "skipped job needed by a selected one",
+                CliOption {
+                    skip: strvec!("group:e2e"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "unknown skipped job",
+                CliOption {
+                    skip: strvec!("unknown"),
+                    ..Default::default()
+                },
+            ),
+        ];
+
+        for (case, cli_option) in cases {
+            assert!(run_ci(&cli_option, config.clone()).is_err(), "{case}");
         }
     }
+
+    #[test]
+    pub fn only_keeps_constraints_between_selected_jobs() {
+        let config = Config {
+            jobs: vec![
+                JobDesc {
+                    script: strvec!("ko:failed"),
+                    ..job_desc("a")
+                },
+                job_desc("b"),
+                job_desc("c"),
+            ],
+            constraints: vec![
+                ("a".to_string(), "b".to_string()),
+                ("c".to_string(), "b".to_string()),
+            ],
+            ..Default::default()
+        };
+        let cli_option = CliOption {
+            only: strvec!("a", "b"),
+            ..Default::default()
+        };
+
+        let tracker = run_ci(&cli_option, config).unwrap();
+
+        assert!(!tracker.states.contains_key("c"));
+        assert_eq!(
+            &Progress::Blocked(strvec!("a")),
+            tracker.states["b"].progresses.first().unwrap()
+        );
+        assert_eq!(&Progress::Cancelled, tracker.states["b"].last());
+    }
+
+    #[test]
+    pub fn selection_with_deps() {
+        let config = Config {
+            jobs: vec![job_desc("a"), job_desc("b"), job_desc("c"), job_desc("d")],
+            constraints: vec![
+                ("a".to_string(), "b".to_string()),
+                ("b".to_string(), "c".to_string()),
+            ],
+            ..Default::default()
+        };
+        let cli_option = CliOption {
+            only: strvec!("c"),
+            with_deps: true,
+            ..Default::default()
+        };
+
+        let tracker = run_ci(&cli_option, config).unwrap();
+
+        assert_eq!(
+            vec!["a", "b", "c"],
+            tracker.states.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            &Progress::Blocked(strvec!("a", "b")),
+            tracker.states["c"].progresses.first().unwrap()
+        );
+    }
+
+    #[test]
+    pub fn matrix_selection() {
+        let mut config = Config {
+            jobs: vec![
+                job_desc("build"),
+                job_desc("tests[stable]"),
+                job_desc("tests[beta]"),
+                job_desc("deploy"),
+            ],
+            constraints: vec![
+                ("build".to_string(), "tests".to_string()),
+                ("tests[stable]".to_string(), "deploy".to_string()),
+            ],
+            matrices: HashMap::from([(
+                "tests".to_string(),
+                strvec!("tests[stable]", "tests[beta]"),
+            )]),
+            ..Default::default()
+        };
+        config.expand_constraints();
+        let cli_option = CliOption {
+            only: strvec!("tests", "deploy"),
+            with_deps: true,
+            skip: strvec!("tests[beta]"),
+            ..Default::default()
+        };
+
+        let tracker = run_ci(&cli_option, config).unwrap();
+
+        let mut names: Vec<&String> = tracker.states.keys().collect();
+        names.sort();
+        assert_eq!(
+            vec!["build", "deploy", "tests[beta]", "tests[stable]"],
+            names
+        );
+        assert_eq!(
+            &Progress::Blocked(strvec!("build", "tests[stable]")),
+            tracker.states["deploy"].progresses.first().unwrap()
+        );
+        assert!(matches!(
+            tracker.states["tests[beta]"].progresses.first().unwrap(),
+            Progress::Skipped(_)
+        ));
+    }
+
+    #[test]
+    pub fn globs() {
+        assert!(glob_match("docker-*", "docker-build"));
+        assert!(glob_match("e2e-?", "e2e-1"));
+        assert!(!glob_match("e2e-?", "e2e-10"));
+        assert!(!glob_match("docker", "docker-build"));
+        assert!(glob_match("a.b", "a.b"));
+        assert!(!glob_match("a.b", "axb"));
+        assert!(glob_match("e2e-[12]", "e2e-2"));
+        assert!(glob_match("tests[beta,full]", "tests[beta,full]"));
+        assert!(glob_match("tests[[]beta,*", "tests[beta,full]"));
+    }
+
+    #[test]
+    pub fn skipped_jobs() {
+        let config = Config {
+            jobs: vec![
+                JobDesc {
+                    group: strvec!("e2e"),
+                    ..job_desc("a")
+                },
+                job_desc("docker-build"),
+                job_desc("c"),
+            ],
+            constraints: vec![("a".to_string(), "c".to_string())],
+            ..Default::default()
+        };
+        let skip = |patterns: Vec<String>| {
+            let cli_option = CliOption {
+                skip: patterns,
+                ..Default::default()
+            };
+            run_ci(&cli_option, config.clone())
+        };
+
+        let tracker = skip(strvec!("docker-*")).unwrap();
+        assert_eq!(
+            vec![
+                Progress::Skipped("left out with --skip".to_string()),
+                Progress::Terminated(true)
+            ],
+            tracker.states["docker-build"].progresses
+        );
+        assert_eq!(Some(true), tracker.states["c"].terminated());
+
+        assert!(skip(strvec!("group:e2e", "c")).is_ok());
+    }
+
+    #[test]
+    pub fn unchanged_inputs_skip_the_job() {
+        let config = Config {
+            jobs: vec![JobDesc {
+                inputs: strvec!("Cargo.toml"),
+                ..job_desc("a")
+            }],
+            ..Default::default()
+        };
+        let mut starter = TestJobStarter::default();
+        let mut run = |cli_option: CliOption| {
+            schedule(
+                &cli_option,
+                config.clone(),
+                &mut starter,
+                &mut SilentDisplay {},
+                None,
+            )
+            .unwrap()
+        };
+
+        let skipped = |tracker: &JobProgressTracker| {
+            tracker.states["a"]
+                .progresses
+                .iter()
+                .any(|progress| matches!(progress, Progress::Skipped(_)))
+        };
+
+        assert!(!skipped(&run(CliOption::default())));
+        assert!(skipped(&run(CliOption::default())));
+        assert!(!skipped(&run(CliOption {
+            no_cache: true,
+            ..Default::default()
+        })));
+    }
+
+    #[test]
+    pub fn interruption_cancels_jobs() {
+        let config = Config {
+            jobs: vec![job_desc("a"), job_desc("b")],
+            ..Default::default()
+        };
+
+        let tracker = schedule(
+            &CliOption::default(),
+            config,
+            &mut TestJobStarter {
+                interrupted: true,
+                ..Default::default()
+            },
+            &mut SilentDisplay {},
+            None,
+        )
+        .unwrap();
+
+        assert!(tracker.interrupted);
+        assert!(tracker.has_failed);
+        assert_eq!(&Progress::Cancelled, tracker.states["a"].last());
+        assert_eq!(&Progress::Cancelled, tracker.states["b"].last());
+    }
 }
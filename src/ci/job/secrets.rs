@@ -0,0 +1,132 @@
+use crate::ci::job::{Output, Progress};
+use std::collections::BTreeMap;
+
+pub const MASK: &str = "****";
+
+/// Shorter values would hide too much of the outputs, they are not masked
+pub const MIN_LENGTH: usize = 4;
+
+/// The values of the env keys marked as secret, which never show in the outputs
+#[derive(Eq, PartialEq, Hash, Debug, Clone, Default)]
+pub struct Secrets(Vec<String>);
+
+impl Secrets {
+    pub fn new(keys: &[String], env: &BTreeMap<String, String>) -> Self {
+        let mut values: Vec<String> = vec![];
+        for value in keys.iter().filter_map(|key| env.get(key)) {
+            // output is read line by line, each line of a value is hidden on its own
+            for part in std::iter::once(value.as_str()).chain(value.lines()) {
+                if !too_short(part) && !values.iter().any(|known| known == part) {
+                    values.push(part.to_string());
+                }
+            }
+        }
+        // the longest first, so that no part of a value is left once a shorter one is hidden
+        values.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        Self(values)
+    }
+
+    /// The keys whose value is left unmasked for being too short
+    pub fn unmasked(keys: &[String], env: &BTreeMap<String, String>) -> Vec<String> {
+        keys.iter()
+            .filter(|key| {
+                env.get(*key)
+                    .is_some_and(|value| !value.trim().is_empty() && too_short(value))
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub const fn none() -> Self {
+        Self(vec![])
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn mask(&self, text: &str) -> String {
+        self.0
+            .iter()
+            .fold(text.to_string(), |text, value| text.replace(value, MASK))
+    }
+
+    pub fn mask_output(&self, output: Output) -> Output {
+        match output {
+            Output::Success(stdout, stderr) => {
+                Output::Success(self.mask(&stdout), self.mask(&stderr))
+            }
+            Output::JobError(stdout, stderr, code) => {
+                Output::JobError(self.mask(&stdout), self.mask(&stderr), code)
+            }
+            Output::ProcessError(error) => Output::ProcessError(self.mask(&error)),
+            Output::TimedOut(stdout, stderr) => {
+                Output::TimedOut(self.mask(&stdout), self.mask(&stderr))
+            }
+        }
+    }
+
+    pub fn mask_progress(&self, progress: Progress) -> Progress {
+        if self.is_empty() {
+            return progress;
+        }
+        match progress {
+            Progress::Started(instruction) => Progress::Started(self.mask(&instruction)),
+            Progress::Line(line) => Progress::Line(self.mask(&line)),
+            Progress::Partial(instruction, output) => {
+                Progress::Partial(self.mask(&instruction), self.mask_output(output))
+            }
+            Progress::Hook(hook, instruction, output) => {
+                Progress::Hook(hook, self.mask(&instruction), self.mask_output(output))
+            }
+            Progress::ContainerStart(output) => Progress::ContainerStart(self.mask_output(output)),
+            progress => progress,
+        }
+    }
+}
+
+fn too_short(value: &str) -> bool {
+    value.trim().chars().count() < MIN_LENGTH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strvec;
+
+    #[test]
+    pub fn values_are_masked() {
+        let env = BTreeMap::from([
+            ("TOKEN".to_string(), "s3cr3t".to_string()),
+            ("KEY".to_string(), "first line\nsecond line".to_string()),
+            ("USER".to_string(), "me".to_string()),
+            ("PIN".to_string(), "123".to_string()),
+        ]);
+        let keys = strvec!("TOKEN", "KEY", "PIN", "MISSING");
+        let secrets = Secrets::new(&keys, &env);
+
+        assert_eq!(
+            "curl -H 'token: ****' as me",
+            secrets.mask("curl -H 'token: s3cr3t' as me")
+        );
+        assert_eq!("pin 123", secrets.mask("pin 123"));
+        assert_eq!(strvec!("PIN"), Secrets::unmasked(&keys, &env));
+        assert_eq!(
+            Progress::Partial(
+                "echo $KEY".to_string(),
+                Output::Success("****\n".to_string(), "****".to_string())
+            ),
+            secrets.mask_progress(Progress::Partial(
+                "echo $KEY".to_string(),
+                Output::Success(
+                    "first line\nsecond line\n".to_string(),
+                    "second line".to_string()
+                )
+            ))
+        );
+        assert_eq!(
+            Progress::Line("****".to_string()),
+            secrets.mask_progress(Progress::Line("first line".to_string()))
+        );
+    }
+}
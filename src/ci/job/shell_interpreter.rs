@@ -56,8 +56,9 @@ impl<'a> ShellInterpreter<'a> {
                 }
                 stdout.trim().to_string()
             }
-            Output::JobError(_, stderr) => return Err(anyhow!(stderr)),
-            Output::ProcessError(stderr) => return Err(anyhow!(stderr)),
+            Output::JobError(_, stderr, _)
+            | Output::TimedOut(_, stderr)
+            | Output::ProcessError(stderr) => return Err(anyhow!(stderr)),
         };
 
         let intermediate_map = parse_env_into_map(envlist);
@@ -1,4 +1,14 @@
 use super::*;
+use crate::ci::config::{CliOption, Config, JobDesc};
+use crate::ci::display::silent::Display as SilentDisplay;
+use crate::ci::job::inspection::JobProgress;
+use crate::ci::job::ports::{CommandRunner, SystemFacade};
+use crate::ci::job::schedule::schedule;
+use crate::strvec;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
 
 pub type ScheduleType = (Vec<Job>, Vec<(String, String)>, Vec<String>);
 
@@ -60,3 +70,100 @@ fn job_group(name: &str, group: &str) -> Job {
 pub fn cons(blocking: &str, blocked: &str) -> (String, String) {
     (blocking.to_string(), blocked.to_string())
 }
+
+#[derive(Default)]
+pub struct TestJobStarter {
+    pub interrupted: bool,
+    pub cache: HashMap<String, String>,
+}
+
+impl CommandRunner for TestJobStarter {
+    fn run(&self, args: &str) -> Output {
+        match args.strip_prefix("ko:") {
+            Some(stripped) => Output::JobError(stripped.to_string(), String::new(), 1),
+            None => Output::Success(String::new(), String::new()),
+        }
+    }
+}
+
+impl SystemFacade for TestJobStarter {
+    fn consume_job(&mut self, job: Job, tx: Sender<JobProgress>) {
+        job.start(&TestJobRunner::default(), &tx);
+    }
+
+    fn delay(&mut self) -> usize {
+        0
+    }
+
+    fn interrupted(&self) -> bool {
+        self.interrupted
+    }
+
+    fn cancel_running_jobs(&mut self) {}
+
+    fn is_cached(&self, job: &str, fingerprint: &str) -> bool {
+        self.cache
+            .get(job)
+            .is_some_and(|cached| cached == fingerprint)
+    }
+
+    fn cache(&mut self, job: &str, fingerprint: &str) {
+        self.cache.insert(job.to_string(), fingerprint.to_string());
+    }
+}
+
+#[derive(Default)]
+pub struct TestJobRunner {
+    runs: Cell<u32>,
+}
+impl CommandRunner for TestJobRunner {
+    fn run(&self, job: &str) -> Output {
+        self.runs.set(self.runs.get() + 1);
+        // clippy::option_if_let_else makes a bad suggestion
+        // https://github.com/rust-lang/rust-clippy/issues/8829
+        if let Some((failures, stripped)) = job
+            .strip_prefix("flaky:")
+            .and_then(|rest| rest.split_once(':'))
+        {
+            // fails the given number of times before succeeding
+            if self.runs.get() > failures.parse().unwrap() {
+                Output::Success(stripped.to_string(), String::new())
+            } else {
+                Output::JobError(stripped.to_string(), String::new(), 1)
+            }
+        } else if let Some(stripped) = job.strip_prefix("ok:") {
+            Output::Success(stripped.to_string(), String::new())
+        } else if let Some(stripped) = job.strip_prefix("ko:") {
+            Output::JobError(stripped.to_string(), String::new(), 1)
+        } else if let Some(stripped) = job.strip_prefix("crash:") {
+            Output::ProcessError(stripped.to_string())
+        } else {
+            panic!("Job should begin with ok:, ko:, crash: or flaky: (actual: '{job}')")
+        }
+    }
+}
+
+/// Schedules `config` with a fresh `TestJobStarter` and no display
+pub fn run_ci(cli_option: &CliOption, config: Config) -> anyhow::Result<JobProgressTracker> {
+    schedule(
+        cli_option,
+        config,
+        &mut TestJobStarter::default(),
+        &mut SilentDisplay {},
+        None,
+    )
+}
+
+/// A job of the config whose instruction succeeds
+pub fn job_desc(name: &str) -> JobDesc {
+    JobDesc {
+        name: name.to_string(),
+        script: strvec!("ok:done"),
+        ..Default::default()
+    }
+}
+
+/// A path in the temp dir unique to this test process, `name` tells the tests apart
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("qad-{name}-{}", std::process::id()))
+}
@@ -1,28 +1,46 @@
-use crate::ci::config::CliOption;
+use crate::ci::config::{CliOption, Config as CiConfig, JobDesc};
+use crate::ci::display::events::Display as EventDisplay;
 use crate::ci::display::exhaustive::FullFinalDisplay;
 use crate::ci::display::interactive::Interactive;
+use crate::ci::display::junit::JunitFinalDisplay;
 use crate::ci::display::sequence::Display as SequenceDisplay;
 use crate::ci::display::silent::Display as SilentDisplay;
 use crate::ci::display::summary::Display as SummaryDisplay;
-use crate::ci::display::{FinalDisplayMode, Running};
-use crate::ci::job::inspection::JobProgress;
+use crate::ci::display::{FinalDisplayMode, OutputFormat, Running};
+use crate::ci::graph::{Graph, GraphFormat};
+use crate::ci::history::{History, STATE_DIR};
+use crate::ci::job::container_configuration::ContainerConfiguration;
+use crate::ci::job::dag::Dag;
+use crate::ci::job::inspection::{JobProgress, JobProgressTracker};
 use crate::ci::job::ports::{CommandRunner, FinalCiDisplay, SystemFacade, UserFacade};
-use crate::ci::job::schedule::schedule;
+use crate::ci::job::schedule::{schedule, select_jobs};
+use crate::ci::job::secrets::Secrets;
 use crate::ci::job::Job;
-use crate::ci::job::{Output, ProgressConsumer};
+use crate::ci::job::{Output, Progress, ProgressConsumer};
+use crate::ci::watch::{WatchDisplay, WatchJobStarter, Watcher};
 use crate::config::{Config, Payload};
-use anyhow::Result;
-use std::collections::HashMap;
-use std::process::{Command, Stdio};
-use std::sync::mpsc::Sender;
+use anyhow::{anyhow, Result};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
 use std::thread::{sleep, JoinHandle};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
+pub mod cache;
 pub mod clean;
 pub mod config;
 pub mod display;
+pub mod graph;
+pub mod history;
 pub mod job;
+pub mod watch;
 
 pub struct Ci {}
 
@@ -36,7 +54,7 @@ impl Ci {
         let mut display: Box<dyn UserFacade> = Box::new(SilentDisplay {});
 
         let cli_option = CliOption {
-            job: Some(job),
+            only: vec![job],
             ..Default::default()
         };
 
@@ -52,14 +70,30 @@ impl Ci {
     }
 
     pub fn run(config: &Config, cli_option: &CliOption) -> Result<bool> {
+        Self::run_after(config, cli_option, None)
+    }
+
+    /// Runs the ci, then saves its history, merged into the `previous` one if given
+    fn run_after(
+        config: &Config,
+        cli_option: &CliOption,
+        previous: Option<History>,
+    ) -> Result<bool> {
         let mut payload = Payload::default();
         config.load_with_args_into(&mut payload)?;
         let ci_config = payload.ci;
+        let groups = ci_config
+            .jobs
+            .iter()
+            .filter_map(|job| Some((job.name.clone(), job.group.first()?.clone())))
+            .collect();
 
         let mut stdout = std::io::stdout();
         let output_is_non_interactive = cli_option.no_tty || !atty::is(atty::Stream::Stdout);
 
-        let mut display: Box<dyn UserFacade> = if output_is_non_interactive {
+        let mut display: Box<dyn UserFacade> = if cli_option.format == OutputFormat::Ndjson {
+            Box::new(EventDisplay::new(&mut stdout))
+        } else if output_is_non_interactive {
             Box::new(SilentDisplay {})
         } else {
             match &payload.display.running_display {
@@ -77,9 +111,20 @@ impl Ci {
             payload.env,
         )?;
 
+        let history = History::from(&tracker);
+        let history = match previous {
+            Some(previous) => previous.merge(history),
+            None => history,
+        };
+        if let Err(error) = history.save(Path::new(STATE_DIR)) {
+            eprintln!("{}: could not save the run: {error}", crate::PACKAGE_NAME);
+        }
+
         let mut display: Box<dyn FinalCiDisplay> = match payload.display.final_display {
+            _ if cli_option.format == OutputFormat::Ndjson => Box::new(SilentDisplay {}),
             FinalDisplayMode::Silent => Box::new(SilentDisplay {}),
             FinalDisplayMode::Full => Box::new(FullFinalDisplay::new(&payload.display)),
+            FinalDisplayMode::Junit => Box::new(JunitFinalDisplay::new(&payload.display, groups)),
             FinalDisplayMode::Interactive => {
                 if output_is_non_interactive {
                     Box::new(FullFinalDisplay::new(&payload.display))
@@ -91,25 +136,170 @@ impl Ci {
 
         display.finish(&tracker);
 
-        Ok(!tracker.has_failed)
+        Ok(!(tracker.has_failed || cli_option.strict && tracker.has_allowed_failures))
     }
 
-    pub fn list(config: &Config) -> Result<()> {
+    /// Plays again the jobs of the last run, or only the ones which failed
+    pub fn rerun(config: &Config, cli_option: &CliOption, failed: bool) -> Result<bool> {
+        let history = History::load(Path::new(STATE_DIR))?;
+        let mut only = if failed {
+            history.failed()
+        } else {
+            history.names()
+        };
+
+        // jobs may have been renamed or removed since the last run
         let mut payload = Payload::default();
         config.load_with_args_into(&mut payload)?;
-        let ci_config = payload.ci;
+        only.retain(|name| payload.ci.jobs.iter().any(|job| &job.name == name));
 
-        let mut jobs = ci_config
+        if only.is_empty() {
+            if failed {
+                println!("no failed job in the last run");
+            } else {
+                println!("no job of the last run is left in the config");
+            }
+            return Ok(true);
+        }
+
+        Self::run_after(
+            config,
+            &CliOption {
+                only,
+                ..cli_option.clone()
+            },
+            Some(history),
+        )
+    }
+
+    /// Whether the last run of every job passed, when the watch stops.
+    /// Jobs allowed to fail only fail it with `--strict`
+    fn watch_passed(states: &JobProgressTracker, cli_option: &CliOption) -> bool {
+        states.states.values().all(|collector| {
+            !matches!(collector.last(), Progress::Terminated(false))
+                || collector.allow_failure && !cli_option.strict
+        })
+    }
+
+    /// Runs the selected jobs which have a `watch` list,
+    /// then runs them again whenever the files they watch change, until interrupted
+    pub fn watch(config: &Config, cli_option: &CliOption) -> Result<bool> {
+        let mut payload = Payload::default();
+        config.load_with_args_into(&mut payload)?;
+
+        let selected: Vec<String> = select_jobs(cli_option, &payload.ci)?
+            .iter()
+            .map(|job| job.name().to_string())
+            .collect();
+        let watching: Vec<JobDesc> = payload
+            .ci
             .jobs
             .iter()
+            .filter(|job| selected.contains(&job.name) && !job.watch.is_empty())
             .cloned()
-            .map(|job| job.name)
-            .collect::<Vec<String>>();
+            .collect();
+        if watching.is_empty() {
+            return Err(anyhow!("none of the selected jobs has files to watch"));
+        }
 
-        jobs.sort();
+        let mut watcher = Watcher::new(&watching)?;
+        let mut starter = ParrallelJobStarter::new();
 
-        for name in jobs {
-            println!("{name}");
+        let mut stdout = std::io::stdout();
+        let output_is_non_interactive = cli_option.no_tty || !atty::is(atty::Stream::Stdout);
+        let mut inner: Box<dyn UserFacade> = if output_is_non_interactive {
+            Box::new(SilentDisplay {})
+        } else {
+            Box::new(SummaryDisplay::new(&payload.display, &mut stdout))
+        };
+        let mut display = WatchDisplay::new(&mut *inner);
+
+        let mut jobs: Vec<String> = watching.into_iter().map(|job| job.name).collect();
+        loop {
+            let mut watch_starter = WatchJobStarter::new(&mut starter, &mut watcher, &jobs);
+            let tracker = schedule(
+                &CliOption {
+                    only: jobs.clone(),
+                    with_deps: false,
+                    ..cli_option.clone()
+                },
+                payload.ci.clone(),
+                &mut watch_starter,
+                &mut display,
+                payload.env.clone(),
+            )?;
+            let outdated = watch_starter.into_outdated();
+
+            if starter.interrupted() {
+                return Ok(Self::watch_passed(&display.states, cli_option));
+            }
+            if output_is_non_interactive {
+                FullFinalDisplay::new(&payload.display).finish(&tracker);
+            }
+
+            jobs = Self::requeued(&payload.ci, &tracker, &outdated)?;
+
+            let mut delay = 0;
+            while !watcher.settled() {
+                if starter.interrupted() {
+                    return Ok(Self::watch_passed(&display.states, cli_option));
+                }
+                display.idle(delay);
+                watcher.poll();
+                delay = starter.delay();
+            }
+            for job in watcher.take() {
+                if !jobs.contains(&job) {
+                    jobs.push(job);
+                }
+            }
+        }
+    }
+
+    /// The jobs cancelled by newer changes, and the cancelled jobs needing one of them, which run again.
+    /// The jobs cancelled after a failure stay cancelled
+    fn requeued(
+        ci_config: &CiConfig,
+        tracker: &JobProgressTracker,
+        outdated: &[String],
+    ) -> Result<Vec<String>> {
+        let all_jobs: Vec<Job> = ci_config.jobs.iter().cloned().map(Into::into).collect();
+        let mut jobs = vec![];
+        for (name, collector) in &tracker.states {
+            if !matches!(collector.last(), Progress::Cancelled) {
+                continue;
+            }
+            let needs_outdated = outdated.contains(name)
+                || Dag::prerequisites(
+                    &all_jobs,
+                    &ci_config.constraints,
+                    &ci_config.groups,
+                    std::slice::from_ref(name),
+                )?
+                .iter()
+                .any(|prerequisite| outdated.contains(prerequisite));
+            if needs_outdated {
+                jobs.push(name.clone());
+            }
+        }
+        Ok(jobs)
+    }
+
+    pub fn graph(config: &Config, format: GraphFormat) -> Result<()> {
+        let mut payload = Payload::default();
+        config.load_with_args_into(&mut payload)?;
+
+        print!("{}", Graph::new(&payload.ci)?.render(format));
+        Ok(())
+    }
+
+    pub fn list(config: &Config) -> Result<()> {
+        let mut payload = Payload::default();
+        config.load_with_args_into(&mut payload)?;
+        let ci_config = payload.ci;
+
+        for line in Self::job_list(&ci_config) {
+            println!("{line}");
         }
         ci_config
             .groups
@@ -118,29 +308,77 @@ impl Ci {
 
         Ok(())
     }
+
+    /// The jobs sorted by name, the jobs of each matrix indented below it
+    fn job_list(ci_config: &CiConfig) -> Vec<String> {
+        let cells: HashSet<&String> = ci_config.matrices.values().flatten().collect();
+        let mut jobs: Vec<&String> = ci_config
+            .jobs
+            .iter()
+            .map(|job| &job.name)
+            .filter(|name| !cells.contains(name))
+            .chain(ci_config.matrices.keys())
+            .collect();
+        jobs.sort();
+
+        let mut lines = vec![];
+        for name in jobs {
+            lines.push(name.clone());
+            for cell in ci_config.matrices.get(name).into_iter().flatten() {
+                lines.push(format!("  {cell}"));
+            }
+        }
+        lines
+    }
 }
 
 #[derive(Debug, Default)]
-pub struct DebugJobStarter {}
+pub struct DebugJobStarter {
+    /// The secrets of the job being debugged
+    secrets: Secrets,
+}
 
-impl CommandRunner for DebugJobStarter {
-    fn run(&self, args: &str) -> Output {
-        std::env::var("SHELL").unwrap_or_else(|_| String::from("/bin/bash"));
-        println!("Command: {args}");
+impl DebugJobStarter {
+    fn debug(&self, args: &str, env: &BTreeMap<String, String>) -> Output {
+        println!("Command: {}", self.secrets.mask(args));
         let default_shell = std::env::var("SHELL").unwrap_or_else(|_| String::from("/bin/bash"));
-        let process = Command::new(default_shell)
+        let mut command = Command::new(default_shell);
+        command
             .args(["-xc", args])
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .output();
+            .envs(env)
+            .stdin(Stdio::inherit());
+        // the output is only read through when it may hold secrets, to keep it untouched otherwise
+        let pipe = |secrets: &Secrets| {
+            if secrets.is_empty() {
+                Stdio::inherit()
+            } else {
+                Stdio::piped()
+            }
+        };
+        command
+            .stdout(pipe(&self.secrets))
+            .stderr(pipe(&self.secrets));
+
+        let Ok(mut child) = command.spawn() else {
+            return Output::ProcessError(String::default());
+        };
+        let (tx, rx) = channel();
+        read_lines_in_background(child.stdout.take(), Pipe::Stdout, tx.clone());
+        read_lines_in_background(child.stderr.take(), Pipe::Stderr, tx);
+        for (pipe, line) in rx {
+            match pipe {
+                Pipe::Stdout => print!("{}", self.secrets.mask(&line)),
+                Pipe::Stderr => eprint!("{}", self.secrets.mask(&line)),
+            }
+        }
+        let status = child.wait();
         println!();
-        match process {
-            Ok(output) => {
-                if output.status.success() {
+        match status {
+            Ok(status) => {
+                if status.success() {
                     Output::Success(String::default(), String::default())
                 } else {
-                    Output::JobError(String::default(), String::default())
+                    Output::JobError(String::default(), String::default(), exit_code(status))
                 }
             }
             Err(_) => Output::ProcessError(String::default()),
@@ -148,9 +386,26 @@ impl CommandRunner for DebugJobStarter {
     }
 }
 
+impl CommandRunner for DebugJobStarter {
+    fn run(&self, args: &str) -> Output {
+        self.debug(args, &BTreeMap::new())
+    }
+
+    fn run_streaming(
+        &self,
+        args: &str,
+        env: &BTreeMap<String, String>,
+        _: Option<Duration>,
+        _: &dyn Fn(String),
+    ) -> Output {
+        self.debug(args, env)
+    }
+}
+
 impl SystemFacade for DebugJobStarter {
     fn consume_job(&mut self, job: Job, tx: Sender<JobProgress>) {
         eprintln!("Consuming job: {}", job.name());
+        self.secrets = job.secrets().clone();
         job.start(self, &tx);
     }
 
@@ -159,25 +414,45 @@ impl SystemFacade for DebugJobStarter {
         200
     }
 
-    fn write_env(&self, env: HashMap<String, Vec<String>>) {
-        for (key, vals) in env {
-            std::env::set_var(key, vals.join("\n"));
-        }
+    fn interrupted(&self) -> bool {
+        false
     }
+
+    fn cancel_running_jobs(&mut self) {}
 }
 
 pub struct ParrallelJobStarter {
     threads: Vec<JoinHandle<()>>,
     last_occurence: SystemTime,
+    runner: CommandJobRunner,
+    interrupted: Arc<AtomicBool>,
 }
 
 const AWAIT_TIME: Duration = Duration::from_millis(40);
 
+/// Where the fingerprints of the last successes are kept, within the state directory
+const CACHE_DIR: &str = "cache";
+
+/// Set on SIGINT or SIGTERM, the handlers are registered once for every starter
+static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
 impl ParrallelJobStarter {
     pub fn new() -> Self {
+        let interrupted = INTERRUPTED.get_or_init(|| {
+            let interrupted = Arc::new(AtomicBool::new(false));
+            for signal in [SIGINT, SIGTERM] {
+                // a second signal while the jobs are being cancelled stops qad right away
+                signal_hook::flag::register_conditional_shutdown(signal, 130, interrupted.clone())
+                    .and_then(|_| signal_hook::flag::register(signal, interrupted.clone()))
+                    .unwrap_or_else(|e| panic!("Failure registering signal {signal}: {e}"));
+            }
+            interrupted
+        });
         Self {
             threads: vec![],
             last_occurence: SystemTime::now(),
+            runner: CommandJobRunner::default(),
+            interrupted: interrupted.clone(),
         }
     }
 }
@@ -190,14 +465,34 @@ impl ProgressConsumer for Sender<JobProgress> {
 
 impl CommandRunner for ParrallelJobStarter {
     fn run(&self, args: &str) -> Output {
-        CommandJobRunner {}.run(args)
+        self.runner.run(args)
+    }
+
+    fn run_streaming(
+        &self,
+        args: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+        on_line: &dyn Fn(String),
+    ) -> Output {
+        self.runner.run_streaming(args, env, timeout, on_line)
+    }
+
+    fn run_cleanup(
+        &self,
+        args: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+    ) -> Output {
+        self.runner.run_cleanup(args, env, timeout)
     }
 }
 
 impl SystemFacade for ParrallelJobStarter {
     fn consume_job(&mut self, job: Job, tx: Sender<JobProgress>) {
+        let runner = self.runner.for_job(job.name(), job.container().clone());
         self.threads.push(thread::spawn(move || {
-            job.start(&CommandJobRunner, &tx);
+            job.start(&runner, &tx);
         }));
     }
 
@@ -215,33 +510,548 @@ impl SystemFacade for ParrallelJobStarter {
         millis
     }
 
-    fn write_env(&self, env: HashMap<String, Vec<String>>) {
-        for (key, vals) in env {
-            std::env::set_var(key, vals.join("\n"));
+    fn interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed)
+    }
+
+    fn cancel_running_jobs(&mut self) {
+        self.runner.cancel();
+    }
+
+    fn cancel_job(&mut self, job: &str) {
+        self.runner.cancel_job(job);
+    }
+
+    fn is_cached(&self, job: &str, fingerprint: &str) -> bool {
+        cache::is_cached(&Path::new(STATE_DIR).join(CACHE_DIR), job, fingerprint)
+    }
+
+    fn cache(&mut self, job: &str, fingerprint: &str) {
+        if let Err(error) = cache::store(&Path::new(STATE_DIR).join(CACHE_DIR), job, fingerprint) {
+            eprintln!("{}: could not cache {job}: {error}", crate::PACKAGE_NAME);
         }
     }
 }
 
-fn mute(args: &str) -> Output {
-    let default_shell = std::env::var("SHELL").unwrap_or_else(|_| String::from("/bin/bash"));
-    match Command::new(default_shell).args(["-c", args]).output() {
-        Ok(output) => {
-            let stdout = String::from(std::str::from_utf8(&output.stdout).unwrap());
-            let stderr = String::from(std::str::from_utf8(&output.stderr).unwrap());
-            if output.status.success() {
-                Output::Success(stdout, stderr)
-            } else {
-                Output::JobError(stdout, stderr)
+/// Runs every instruction in its own process group,
+/// so that an instruction and everything it spawned can be killed at once
+#[derive(Clone, Default)]
+pub struct CommandJobRunner {
+    /// The running process groups
+    process_groups: Arc<Mutex<HashMap<u32, RunningInstruction>>>,
+    cancelled: Arc<AtomicBool>,
+    cancelled_jobs: Arc<Mutex<HashSet<String>>>,
+    /// The job whose instructions this runner runs, if any
+    job: Option<String>,
+    /// The container the instructions of the job run in
+    container: ContainerConfiguration,
+    /// Whether this runner runs cleanups, which are not cancelled
+    cleanup: bool,
+}
+
+impl CommandJobRunner {
+    /// A runner for the instructions of `job`, which may be cancelled on its own.
+    /// A new run of the job is no longer cancelled
+    fn for_job(&self, job: &str, container: ContainerConfiguration) -> Self {
+        self.cancelled_jobs.lock().unwrap().remove(job);
+        Self {
+            job: Some(job.to_string()),
+            container,
+            ..self.clone()
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        !self.cleanup
+            && (self.cancelled.load(Ordering::Relaxed)
+                || self
+                    .job
+                    .as_ref()
+                    .is_some_and(|job| self.cancelled_jobs.lock().unwrap().contains(job)))
+    }
+
+    fn mute(
+        &self,
+        args: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+        on_line: &dyn Fn(String),
+    ) -> Output {
+        let default_shell = std::env::var("SHELL").unwrap_or_else(|_| String::from("/bin/bash"));
+        let mut command = Command::new(default_shell);
+        command
+            .args(["-c", args])
+            .envs(env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0);
+
+        let (mut child, kill) = {
+            let mut process_groups = self.process_groups.lock().unwrap();
+            if self.is_cancelled() {
+                return Output::ProcessError(String::from("cancelled"));
+            }
+            match command.spawn() {
+                Ok(child) => {
+                    let kill = self
+                        .job
+                        .as_ref()
+                        .and_then(|job| self.container.kill(job, child.id()));
+                    let running = RunningInstruction {
+                        job: self.job.clone(),
+                        kill: kill.clone(),
+                    };
+                    process_groups.insert(child.id(), running);
+                    drop(process_groups);
+                    (child, kill)
+                }
+                Err(e) => return Output::ProcessError(e.to_string()),
+            }
+        };
+        let (tx, rx) = channel();
+        read_lines_in_background(child.stdout.take(), Pipe::Stdout, tx.clone());
+        read_lines_in_background(child.stderr.take(), Pipe::Stderr, tx);
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        let mut timed_out = false;
+        let mut exited = false;
+        loop {
+            let wait = deadline.map_or(AWAIT_TIME, |deadline| {
+                AWAIT_TIME.min(deadline.saturating_duration_since(Instant::now()))
+            });
+            match rx.recv_timeout(wait) {
+                Ok((pipe, line)) => {
+                    on_line(line.trim_end_matches(['\n', '\r']).to_string());
+                    match pipe {
+                        Pipe::Stdout => stdout.push_str(&line),
+                        Pipe::Stderr => stderr.push_str(&line),
+                    }
+                }
+                // a process left in the background may hold the pipes open,
+                // so the instruction is over once it exited and its output stopped coming
+                Err(RecvTimeoutError::Timeout) if exited => break,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            exited = exited || matches!(child.try_wait(), Ok(Some(_)));
+            if exited && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break;
+            }
+            if !exited && !timed_out && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                kill_instruction(child.id(), kill.as_deref());
+                timed_out = true;
             }
         }
-        Err(e) => Output::ProcessError(e.to_string()),
+
+        let status = child.wait();
+        self.process_groups.lock().unwrap().remove(&child.id());
+
+        match status {
+            Ok(_) if timed_out => Output::TimedOut(stdout, stderr),
+            Ok(status) if status.success() => Output::Success(stdout, stderr),
+            Ok(status) => Output::JobError(stdout, stderr, exit_code(status)),
+            Err(e) => Output::ProcessError(e.to_string()),
+        }
+    }
+
+    /// Kills every running instruction and refuses to start new ones
+    fn cancel(&self) {
+        let process_groups = self.process_groups.lock().unwrap();
+        self.cancelled.store(true, Ordering::Relaxed);
+        for (process_group, running) in process_groups.iter() {
+            kill_instruction(*process_group, running.kill.as_deref());
+        }
     }
+
+    /// Kills the running instruction of `job` and refuses to start its next ones
+    fn cancel_job(&self, job: &str) {
+        let process_groups = self.process_groups.lock().unwrap();
+        self.cancelled_jobs.lock().unwrap().insert(job.to_string());
+        for (process_group, running) in process_groups
+            .iter()
+            .filter(|(_, running)| running.job.as_deref() == Some(job))
+        {
+            kill_instruction(*process_group, running.kill.as_deref());
+        }
+    }
+}
+
+/// An instruction running in its own process group
+struct RunningInstruction {
+    /// The job it belongs to, if any
+    job: Option<String>,
+    /// The command killing what it runs in a container, if it runs in one
+    kill: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Pipe {
+    Stdout,
+    Stderr,
+}
+
+/// Forwards the output of `pipe` line by line, until it is closed
+fn read_lines_in_background(
+    pipe: Option<impl Read + Send + 'static>,
+    kind: Pipe,
+    tx: Sender<(Pipe, String)>,
+) {
+    let Some(pipe) = pipe else {
+        return;
+    };
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut buffer = vec![];
+        while let Ok(read) = reader.read_until(b'\n', &mut buffer) {
+            if read == 0 {
+                break;
+            }
+            let line = String::from_utf8_lossy(&buffer).to_string();
+            buffer.clear();
+            if tx.send((kind, line)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// The code an instruction exited with, or 128 plus the signal which killed it, like shells do
+fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
 }
 
-pub struct CommandJobRunner;
+fn kill_process_group(process_group: u32) {
+    if let Ok(pid) = libc::pid_t::try_from(process_group) {
+        // SAFETY: kill has no memory safety requirements, a negative pid targets a process group
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+}
+
+/// Kills the process group of an instruction, then what it runs in a container with `kill`
+fn kill_instruction(process_group: u32, kill: Option<&str>) {
+    kill_process_group(process_group);
+    if let Some(kill) = kill {
+        Command::new("sh")
+            .args(["-c", kill])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .ok();
+    }
+}
 
 impl CommandRunner for CommandJobRunner {
     fn run(&self, args: &str) -> Output {
-        mute(args)
+        self.mute(args, &BTreeMap::new(), None, &|_| {})
+    }
+
+    fn run_streaming(
+        &self,
+        args: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+        on_line: &dyn Fn(String),
+    ) -> Output {
+        self.mute(args, env, timeout, on_line)
+    }
+
+    fn run_cleanup(
+        &self,
+        args: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+    ) -> Output {
+        let cleanup = Self {
+            cleanup: true,
+            ..self.clone()
+        };
+        cleanup.mute(args, env, timeout, &|_| {})
+    }
+
+    fn cancelled(&self) -> bool {
+        self.is_cancelled()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::job::{Failure, Hook, HookScript, Retry};
+    use crate::strvec;
+    use std::cell::RefCell;
+
+    #[test]
+    fn timeout_kills_the_instruction() {
+        let start = Instant::now();
+        let output = CommandJobRunner::default().run_streaming(
+            "echo started; sleep 5",
+            &BTreeMap::new(),
+            Some(Duration::from_millis(100)),
+            &|_| {},
+        );
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            Output::TimedOut("started\n".to_string(), String::new()),
+            output
+        );
+    }
+
+    #[test]
+    fn no_timeout() {
+        assert_eq!(
+            Output::Success("done\n".to_string(), String::new()),
+            CommandJobRunner::default().run_streaming(
+                "echo done",
+                &BTreeMap::new(),
+                Some(Duration::from_secs(5)),
+                &|_| {}
+            )
+        );
+    }
+
+    #[test]
+    fn background_processes_do_not_hold_the_instruction() {
+        let start = Instant::now();
+        let output = CommandJobRunner::default().run_streaming(
+            "sleep 5 & echo done",
+            &BTreeMap::new(),
+            None,
+            &|_| {},
+        );
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(Output::Success("done\n".to_string(), String::new()), output);
+    }
+
+    #[test]
+    fn cancelling_a_job_leaves_the_other_ones_running() {
+        let runner = CommandJobRunner::default();
+        let outdated = runner.for_job("outdated", ContainerConfiguration::None);
+        let other = runner.for_job("other", ContainerConfiguration::None);
+        let start = Instant::now();
+
+        let next_instruction = outdated.clone();
+        let outdated = thread::spawn(move || outdated.run("sleep 5"));
+        let other = thread::spawn(move || other.run("sleep 0.3; echo done"));
+        sleep(Duration::from_millis(100));
+        runner.cancel_job("outdated");
+
+        assert!(matches!(
+            outdated.join().unwrap(),
+            Output::JobError(_, _, _)
+        ));
+        assert_eq!(
+            Output::Success("done\n".to_string(), String::new()),
+            other.join().unwrap()
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            Output::ProcessError("cancelled".to_string()),
+            next_instruction.run("echo again")
+        );
+    }
+
+    #[test]
+    fn matrix_jobs_are_listed_with_their_cells() {
+        let job = |name: &str| JobDesc {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let config = CiConfig {
+            jobs: vec![
+                job("tests[stable]"),
+                job("tests[beta]"),
+                job("tests-e2e"),
+                job("fmt"),
+            ],
+            matrices: HashMap::from([(
+                "tests".to_string(),
+                strvec!("tests[stable]", "tests[beta]"),
+            )]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            strvec!(
+                "fmt",
+                "tests",
+                "  tests[stable]",
+                "  tests[beta]",
+                "tests-e2e"
+            ),
+            Ci::job_list(&config)
+        );
+    }
+
+    #[test]
+    fn cleanups_run_when_the_ci_is_interrupted() {
+        let config = CiConfig {
+            jobs: vec![JobDesc {
+                name: "e2e".to_string(),
+                script: strvec!("sleep 5"),
+                after_script: HookScript {
+                    instructions: strvec!("echo cleaned"),
+                    fatal: true,
+                },
+                ..Default::default()
+            }],
+            teardown: HookScript {
+                instructions: strvec!("echo torn down"),
+                fatal: true,
+            },
+            ..Default::default()
+        };
+        let mut starter = ParrallelJobStarter::new();
+        // the flag of the signal handlers is shared by the whole process, the test has its own
+        starter.interrupted = Arc::new(AtomicBool::new(false));
+        let interrupted = starter.interrupted.clone();
+        thread::spawn(move || {
+            sleep(Duration::from_millis(200));
+            interrupted.store(true, Ordering::Relaxed);
+        });
+
+        let start = Instant::now();
+        let tracker = schedule(
+            &CliOption::default(),
+            config,
+            &mut starter,
+            &mut SilentDisplay {},
+            None,
+        )
+        .unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(tracker.interrupted);
+        assert!(tracker.states["e2e"].progresses.contains(&Progress::Hook(
+            Hook::AfterScript,
+            "echo cleaned".to_string(),
+            Output::Success("cleaned\n".to_string(), String::new())
+        )));
+        assert_eq!(
+            &Progress::Hook(
+                Hook::Teardown,
+                "echo torn down".to_string(),
+                Output::Success("torn down\n".to_string(), String::new())
+            ),
+            tracker.hooks.last()
+        );
+        assert_eq!(
+            Output::ProcessError("cancelled".to_string()),
+            starter.run("echo again")
+        );
+    }
+
+    #[test]
+    fn watch_fails_on_the_last_failures() {
+        let mut states = JobProgressTracker::new();
+        states.record(JobProgress::new("audit", Progress::Terminated(false)));
+        states.states["audit"].allow_failure = true;
+        let strict = CliOption {
+            strict: true,
+            ..Default::default()
+        };
+
+        assert!(Ci::watch_passed(&states, &CliOption::default()));
+        assert!(!Ci::watch_passed(&states, &strict));
+
+        states.record(JobProgress::new("tests", Progress::Terminated(false)));
+        assert!(!Ci::watch_passed(&states, &CliOption::default()));
+    }
+
+    #[test]
+    fn only_outdated_jobs_and_their_dependents_run_again() {
+        let job = |name: &str| JobDesc {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let config = CiConfig {
+            jobs: vec![job("build"), job("tests"), job("lint"), job("deploy")],
+            constraints: vec![
+                ("build".to_string(), "tests".to_string()),
+                ("lint".to_string(), "deploy".to_string()),
+            ],
+            ..Default::default()
+        };
+        let mut tracker = JobProgressTracker::new();
+        for name in ["build", "tests", "deploy"] {
+            tracker.record(JobProgress::new(name, Progress::Cancelled));
+        }
+        tracker.record(JobProgress::new("lint", Progress::Terminated(false)));
+
+        assert_eq!(
+            strvec!("build", "tests"),
+            Ci::requeued(&config, &tracker, &["build".to_string()]).unwrap()
+        );
+    }
+
+    #[test]
+    fn cancelled_instructions_are_not_retried() {
+        let runner = CommandJobRunner::default().for_job("e2e", ContainerConfiguration::None);
+        let job = Job::long("e2e".to_string(), strvec!("sleep 5"), None, None).with_retry(Retry {
+            max: 3,
+            when: vec![Failure::JobError],
+        });
+        let canceller = runner.clone();
+        thread::spawn(move || {
+            sleep(Duration::from_millis(100));
+            canceller.cancel_job("e2e");
+        });
+
+        let start = Instant::now();
+        let (tx, rx) = channel();
+        job.start(&runner, &tx);
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let progresses: Vec<Progress> = rx.try_iter().map(|progress| progress.1).collect();
+        assert!(!progresses
+            .iter()
+            .any(|progress| matches!(progress, Progress::Retrying(_, _))));
+        assert_eq!(Some(&Progress::Terminated(false)), progresses.last());
+    }
+
+    #[test]
+    fn lines_are_streamed() {
+        let lines = RefCell::new(vec![]);
+        let output = CommandJobRunner::default().run_streaming(
+            "echo out; echo err >&2",
+            &BTreeMap::new(),
+            None,
+            &|line| {
+                lines.borrow_mut().push(line);
+            },
+        );
+
+        let mut lines = lines.into_inner();
+        lines.sort();
+        assert_eq!(strvec!("err", "out"), lines);
+        assert_eq!(
+            Output::Success("out\n".to_string(), "err\n".to_string()),
+            output
+        );
+    }
+
+    #[test]
+    fn env_is_given_to_the_instruction_only() {
+        let env = BTreeMap::from([("QAD_TEST_VARIABLE".to_string(), "job value".to_string())]);
+        assert_eq!(
+            Output::Success("job value\n".to_string(), String::new()),
+            CommandJobRunner::default().run_streaming(
+                "echo \"$QAD_TEST_VARIABLE\"",
+                &env,
+                None,
+                &|_| {}
+            )
+        );
+        assert!(std::env::var("QAD_TEST_VARIABLE").is_err());
     }
 }
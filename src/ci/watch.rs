@@ -0,0 +1,316 @@
+use crate::ci::config::JobDesc;
+use crate::ci::job::inspection::{JobProgress, JobProgressTracker};
+use crate::ci::job::ports::{CommandRunner, SystemFacade, UserFacade};
+use crate::ci::job::{Job, Output};
+use crate::ci::ParrallelJobStarter;
+use anyhow::{anyhow, Result};
+use glob::Pattern;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the watched files are looked at
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// How long the files must stay untouched before the jobs run again,
+/// so that a burst of saves triggers a single run
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Polls the files matching the `watch` globs of the jobs
+pub struct Watcher {
+    jobs: Vec<(String, Vec<Pattern>)>,
+    files: HashMap<PathBuf, (Option<SystemTime>, u64)>,
+    changed: BTreeSet<PathBuf>,
+    last_scan: Instant,
+    last_change: Instant,
+}
+
+impl Watcher {
+    pub fn new(jobs: &[JobDesc]) -> Result<Self> {
+        let jobs = jobs
+            .iter()
+            .map(|job| {
+                let patterns = job
+                    .watch
+                    .iter()
+                    .map(|pattern| {
+                        Pattern::new(pattern).map_err(|error| {
+                            anyhow!("invalid watch pattern {pattern} in {}: {error}", job.name)
+                        })
+                    })
+                    .collect::<Result<Vec<Pattern>>>()?;
+                Ok((job.name.clone(), patterns))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut watcher = Self {
+            jobs,
+            files: HashMap::new(),
+            changed: BTreeSet::new(),
+            last_scan: Instant::now(),
+            last_change: Instant::now(),
+        };
+        watcher.files = watcher.scan();
+        Ok(watcher)
+    }
+
+    fn scan(&self) -> HashMap<PathBuf, (Option<SystemTime>, u64)> {
+        let mut files = HashMap::new();
+        for pattern in self.jobs.iter().flat_map(|(_, patterns)| patterns) {
+            let Ok(paths) = glob::glob(pattern.as_str()) else {
+                continue;
+            };
+            for path in paths.filter_map(Result::ok) {
+                if let Ok(metadata) = path.metadata() {
+                    if metadata.is_file() {
+                        files.insert(path, (metadata.modified().ok(), metadata.len()));
+                    }
+                }
+            }
+        }
+        files
+    }
+
+    /// Looks for changes, at most once every `POLL_INTERVAL`,
+    /// and tells whether new ones were found
+    pub fn poll(&mut self) -> bool {
+        if self.last_scan.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.poll_now()
+    }
+
+    fn poll_now(&mut self) -> bool {
+        self.last_scan = Instant::now();
+        let files = self.scan();
+        let before = self.changed.len();
+        for (path, state) in &files {
+            if self.files.get(path) != Some(state) {
+                self.changed.insert(path.clone());
+            }
+        }
+        for path in self.files.keys() {
+            if !files.contains_key(path) {
+                self.changed.insert(path.clone());
+            }
+        }
+        self.files = files;
+
+        let found = self.changed.len() > before;
+        if found {
+            self.last_change = Instant::now();
+        }
+        found
+    }
+
+    /// The jobs watching one of the changed files
+    pub fn affected(&self) -> Vec<String> {
+        self.jobs
+            .iter()
+            .filter(|(_, patterns)| {
+                self.changed
+                    .iter()
+                    .any(|path| patterns.iter().any(|pattern| pattern.matches_path(path)))
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Whether files changed, and were left untouched since long enough
+    pub fn settled(&self) -> bool {
+        !self.changed.is_empty() && self.last_change.elapsed() >= DEBOUNCE
+    }
+
+    /// The jobs to run again, forgetting about the changes
+    pub fn take(&mut self) -> Vec<String> {
+        let affected = self.affected();
+        self.changed.clear();
+        affected
+    }
+}
+
+/// Runs the jobs like `ParrallelJobStarter`, but cancels a running job
+/// when it is affected by newer changes, leaving the other ones running
+pub struct WatchJobStarter<'a> {
+    starter: &'a mut ParrallelJobStarter,
+    watcher: &'a mut Watcher,
+    /// The jobs of the current run
+    jobs: &'a [String],
+    /// The jobs cancelled as outdated during the current run
+    outdated: Vec<String>,
+}
+
+impl<'a> WatchJobStarter<'a> {
+    pub const fn new(
+        starter: &'a mut ParrallelJobStarter,
+        watcher: &'a mut Watcher,
+        jobs: &'a [String],
+    ) -> Self {
+        Self {
+            starter,
+            watcher,
+            jobs,
+            outdated: vec![],
+        }
+    }
+
+    /// The jobs cancelled as outdated during the run
+    pub fn into_outdated(self) -> Vec<String> {
+        self.outdated
+    }
+}
+
+impl CommandRunner for WatchJobStarter<'_> {
+    fn run(&self, args: &str) -> Output {
+        self.starter.run(args)
+    }
+
+    fn run_streaming(
+        &self,
+        args: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+        on_line: &dyn Fn(String),
+    ) -> Output {
+        self.starter.run_streaming(args, env, timeout, on_line)
+    }
+
+    fn run_cleanup(
+        &self,
+        args: &str,
+        env: &BTreeMap<String, String>,
+        timeout: Option<Duration>,
+    ) -> Output {
+        self.starter.run_cleanup(args, env, timeout)
+    }
+}
+
+impl SystemFacade for WatchJobStarter<'_> {
+    fn consume_job(&mut self, job: Job, tx: Sender<JobProgress>) {
+        self.starter.consume_job(job, tx);
+    }
+
+    fn delay(&mut self) -> usize {
+        self.starter.delay()
+    }
+
+    fn interrupted(&self) -> bool {
+        self.starter.interrupted()
+    }
+
+    fn cancel_running_jobs(&mut self) {
+        self.starter.cancel_running_jobs();
+    }
+
+    fn outdated_jobs(&mut self) -> Vec<String> {
+        if !self.watcher.poll() {
+            return vec![];
+        }
+        self.watcher
+            .affected()
+            .into_iter()
+            .filter(|job| self.jobs.contains(job))
+            .collect()
+    }
+
+    fn cancel_job(&mut self, job: &str) {
+        self.starter.cancel_job(job);
+        self.outdated.push(job.to_string());
+    }
+
+    fn is_cached(&self, job: &str, fingerprint: &str) -> bool {
+        self.starter.is_cached(job, fingerprint)
+    }
+
+    fn cache(&mut self, job: &str, fingerprint: &str) {
+        self.starter.cache(job, fingerprint);
+    }
+}
+
+/// Shows the jobs of the current run along with the last state of the other ones
+pub struct WatchDisplay<'a> {
+    display: &'a mut dyn UserFacade,
+    pub states: JobProgressTracker,
+}
+
+impl<'a> WatchDisplay<'a> {
+    pub fn new(display: &'a mut dyn UserFacade) -> Self {
+        Self {
+            display,
+            states: JobProgressTracker::new(),
+        }
+    }
+
+    fn merged(&self, tracker: &JobProgressTracker) -> JobProgressTracker {
+        let mut merged = self.states.clone();
+        for (name, collector) in &tracker.states {
+            merged.states.insert(name.clone(), collector.clone());
+        }
+        merged
+    }
+
+    /// Refreshes the display between two runs
+    pub fn idle(&mut self, elapsed: usize) {
+        self.display.run(&self.states, elapsed);
+    }
+}
+
+impl UserFacade for WatchDisplay<'_> {
+    fn set_up(&mut self, tracker: &JobProgressTracker) {
+        let merged = self.merged(tracker);
+        self.display.set_up(&merged);
+    }
+
+    fn run(&mut self, tracker: &JobProgressTracker, elapsed: usize) {
+        let merged = self.merged(tracker);
+        self.display.run(&merged, elapsed);
+    }
+
+    /// The display is kept on screen until the watch stops
+    fn tear_down(&mut self, tracker: &JobProgressTracker) {
+        self.states = self.merged(tracker);
+        self.display.run(&self.states, 0);
+    }
+
+    fn display_error(&self, error: String) {
+        self.display.display_error(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::job::tests::temp_path;
+    use crate::strvec;
+    use std::fs;
+
+    #[test]
+    pub fn changes_affect_watching_jobs() {
+        let dir = temp_path("watch");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.join("README.md"), "# qad").unwrap();
+
+        let job = |name: &str, pattern: &str| JobDesc {
+            name: name.to_string(),
+            watch: vec![format!("{}/{pattern}", dir.display())],
+            ..Default::default()
+        };
+        let mut watcher = Watcher::new(&[job("build", "*.rs"), job("docs", "*.md")]).unwrap();
+
+        assert!(!watcher.poll_now());
+        assert!(!watcher.settled());
+
+        fs::write(dir.join("main.rs"), "fn main() { todo!() }").unwrap();
+        assert!(watcher.poll_now());
+        assert_eq!(strvec!("build"), watcher.affected());
+
+        fs::remove_file(dir.join("README.md")).unwrap();
+        assert!(watcher.poll_now());
+        assert_eq!(strvec!("build", "docs"), watcher.take());
+        assert!(watcher.affected().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,5 @@
+use crate::ci::display::OutputFormat;
+use crate::ci::graph::GraphFormat;
 use argh::FromArgs;
 
 #[derive(FromArgs, Eq, PartialEq, Debug)]
@@ -24,6 +26,10 @@ pub struct Args {
 #[argh(subcommand)]
 pub enum Subcommands {
     Ci(CiArgs),
+    Rerun(RerunArgs),
+    Watch(WatchArgs),
+    Graph(GraphArgs),
+    Check(CheckArgs),
     List(ListArgs),
     Autocomplete(AutocompleteArgs),
     HasCi(HasCiArgs),
@@ -31,13 +37,152 @@ pub enum Subcommands {
     Debug(DebugArgs),
 }
 
+#[allow(clippy::struct_excessive_bools)]
 #[derive(FromArgs, Eq, PartialEq, Debug)]
 #[argh(subcommand, name = "ci", description = "play the ci")]
 pub struct CiArgs {
-    #[argh(positional, description = "an optionnal job or group to run")]
-    pub nested: Option<String>,
+    #[argh(
+        positional,
+        description = "jobs or groups (group:name) to run instead of the whole ci"
+    )]
+    pub nested: Vec<String>,
+
+    #[argh(switch, description = "also run the jobs the selection depends on")]
+    pub with_deps: bool,
+
+    #[argh(
+        option,
+        description = "job or group (group:name) to leave out, as a name or a glob"
+    )]
+    pub skip: Vec<String>,
+
+    #[argh(
+        switch,
+        description = "run the jobs even if their inputs did not change"
+    )]
+    pub no_cache: bool,
+
+    #[argh(
+        switch,
+        description = "after a failure, only cancel the jobs which need the failed one"
+    )]
+    pub keep_going: bool,
+
+    #[argh(
+        switch,
+        description = "fail even when only jobs allowed to fail failed"
+    )]
+    pub strict: bool,
+
+    #[argh(
+        option,
+        short = 'j',
+        description = "maximum number of jobs running at the same time"
+    )]
+    pub jobs: Option<usize>,
+
+    #[argh(
+        option,
+        default = "OutputFormat::Human",
+        description = "output format: human (default) or ndjson"
+    )]
+    pub format: OutputFormat,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(FromArgs, Eq, PartialEq, Debug)]
+#[argh(
+    subcommand,
+    name = "rerun",
+    description = "play again the jobs of the last ci"
+)]
+pub struct RerunArgs {
+    #[argh(switch, description = "only the jobs which failed or were cancelled")]
+    pub failed: bool,
+
+    #[argh(
+        switch,
+        description = "run the jobs even if their inputs did not change"
+    )]
+    pub no_cache: bool,
+
+    #[argh(
+        switch,
+        description = "after a failure, only cancel the jobs which need the failed one"
+    )]
+    pub keep_going: bool,
+
+    #[argh(
+        switch,
+        description = "fail even when only jobs allowed to fail failed"
+    )]
+    pub strict: bool,
+
+    #[argh(
+        option,
+        short = 'j',
+        description = "maximum number of jobs running at the same time"
+    )]
+    pub jobs: Option<usize>,
+
+    #[argh(
+        option,
+        default = "OutputFormat::Human",
+        description = "output format: human (default) or ndjson"
+    )]
+    pub format: OutputFormat,
+}
+
+#[derive(FromArgs, Eq, PartialEq, Debug)]
+#[argh(
+    subcommand,
+    name = "watch",
+    description = "play the jobs again when the files they watch change"
+)]
+pub struct WatchArgs {
+    #[argh(
+        positional,
+        description = "jobs or groups (group:name) to watch instead of the whole ci"
+    )]
+    pub nested: Vec<String>,
+
+    #[argh(
+        switch,
+        description = "fail even when only jobs allowed to fail failed"
+    )]
+    pub strict: bool,
+
+    #[argh(
+        option,
+        short = 'j',
+        description = "maximum number of jobs running at the same time"
+    )]
+    pub jobs: Option<usize>,
 }
 
+#[derive(FromArgs, Eq, PartialEq, Debug)]
+#[argh(
+    subcommand,
+    name = "graph",
+    description = "show the order between the jobs"
+)]
+pub struct GraphArgs {
+    #[argh(
+        option,
+        default = "GraphFormat::Text",
+        description = "output format: text (default), dot or mermaid"
+    )]
+    pub format: GraphFormat,
+}
+
+#[derive(FromArgs, Eq, PartialEq, Debug)]
+#[argh(
+    subcommand,
+    name = "check",
+    description = "report every problem of the config and its extra files"
+)]
+pub struct CheckArgs {}
+
 #[derive(FromArgs, Eq, PartialEq, Debug)]
 #[argh(subcommand, name = "list", description = "list jobs")]
 pub struct ListArgs {}
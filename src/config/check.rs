@@ -0,0 +1,571 @@
+use crate::ci::config::JobDesc;
+use crate::ci::graph::Graph;
+use crate::config::versions::version_1::{keys, Version1};
+use crate::config::{Config, Error, Payload};
+use anyhow::Result;
+use regex::Regex;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::sync::LazyLock;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::Marker;
+
+/// The location `serde_yaml` appends to its errors
+static LOCATION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r" at line \d+ column \d+$").unwrap());
+
+/// Something wrong in a config file, at a given line and column
+#[derive(Debug, Eq, PartialEq)]
+pub struct Problem {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+/// A config file, with its content to locate the problems in
+struct Source {
+    file: String,
+    text: String,
+    /// Line and column of each key, by the keys leading to it
+    keys: HashMap<Vec<String>, (usize, usize)>,
+}
+
+impl Source {
+    fn new(file: String, text: String) -> Self {
+        let keys = locate_keys(&text);
+        Self { file, text, keys }
+    }
+
+    /// The problem at the key at the end of `path`. A missing key has no position,
+    /// so the problem is at the deepest key of `path` the file has, or at the start of the file
+    fn problem(&self, path: &[&str], message: String) -> Problem {
+        let (line, column) = (0..=path.len())
+            .rev()
+            .find_map(|depth| {
+                let keys: Vec<String> = path[..depth].iter().map(ToString::to_string).collect();
+                self.keys.get(&keys).copied()
+            })
+            .unwrap_or((1, 1));
+        Problem {
+            file: self.file.clone(),
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+/// A collection being parsed
+enum Node {
+    /// The key whose value is being parsed, if any
+    Mapping(Option<String>),
+    /// The index of the item being parsed
+    Sequence(usize),
+}
+
+/// Records the position of the keys from the events of the yaml parser
+#[derive(Default)]
+struct KeyLocator {
+    nodes: Vec<Node>,
+    keys: HashMap<Vec<String>, (usize, usize)>,
+}
+
+impl KeyLocator {
+    fn path(&self) -> Vec<String> {
+        self.nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Mapping(key) => key.clone(),
+                Node::Sequence(index) => Some(index.to_string()),
+            })
+            .collect()
+    }
+
+    /// A value was parsed, the collection holding it moves on to its next key or item
+    fn next(&mut self) {
+        match self.nodes.last_mut() {
+            Some(Node::Mapping(key)) => {
+                // a key which is a collection leaves a value to parse, under no name
+                *key = if key.is_some() {
+                    None
+                } else {
+                    Some(String::new())
+                };
+            }
+            Some(Node::Sequence(index)) => *index += 1,
+            None => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for KeyLocator {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::MappingStart(..) => self.nodes.push(Node::Mapping(None)),
+            Event::SequenceStart(..) => self.nodes.push(Node::Sequence(0)),
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.nodes.pop();
+                self.next();
+            }
+            Event::Scalar(value, ..) => {
+                if matches!(self.nodes.last(), Some(Node::Mapping(None))) {
+                    let mut path = self.path();
+                    path.push(value.clone());
+                    self.keys.insert(path, (mark.line(), mark.col() + 1));
+                    self.nodes.pop();
+                    self.nodes.push(Node::Mapping(Some(value)));
+                } else {
+                    self.next();
+                }
+            }
+            Event::Alias(_) => self.next(),
+            _ => {}
+        }
+    }
+}
+
+/// Line and column of each key of `text`, in block or flow style.
+/// Empty when the text is not valid yaml, which parsing reports anyway
+fn locate_keys(text: &str) -> HashMap<Vec<String>, (usize, usize)> {
+    let mut locator = KeyLocator::default();
+    if Parser::new_from_str(text)
+        .load(&mut locator, false)
+        .is_err()
+    {
+        return HashMap::new();
+    }
+    locator.keys
+}
+
+/// Distance between two words, to suggest the key a typo meant
+fn distance(left: &str, right: &str) -> usize {
+    let right: Vec<char> = right.chars().collect();
+    let mut previous: Vec<usize> = (0..=right.len()).collect();
+    for (i, left_char) in left.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, right_char) in right.iter().enumerate() {
+            let substitution = previous[j] + usize::from(left_char != *right_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[right.len()]
+}
+
+fn unknown_keys(source: &Source, path: &[&str], mapping: &Mapping, known: &[&str]) -> Vec<Problem> {
+    mapping
+        .keys()
+        .filter_map(Value::as_str)
+        .filter(|key| !known.contains(key))
+        .map(|key| {
+            let suggestion = known
+                .iter()
+                .filter(|candidate| distance(key, candidate) <= 2)
+                .min_by_key(|candidate| distance(key, candidate))
+                .map(|candidate| format!(" (did you mean {candidate}?)"))
+                .unwrap_or_default();
+            let mut at = path.to_vec();
+            at.push(key);
+            source.problem(&at, format!("unknown key {key}{suggestion}"))
+        })
+        .collect()
+}
+
+/// Keys the version 1 format does not know of
+fn schema(source: &Source, root: &Mapping) -> Vec<Problem> {
+    let mut problems = unknown_keys(source, &[], root, keys::ROOT);
+    let mapping = |key: &str| root.get(key).and_then(Value::as_mapping);
+
+    for section in ["jobs", "templates"] {
+        for (name, job) in mapping(section).into_iter().flatten() {
+            let (Some(name), Some(job)) = (name.as_str(), job.as_mapping()) else {
+                continue;
+            };
+            problems.extend(unknown_keys(source, &[section, name], job, keys::JOB));
+            if let Some(image) = job.get("image").and_then(Value::as_mapping) {
+                problems.extend(unknown_keys(
+                    source,
+                    &[section, name, "image"],
+                    image,
+                    keys::IMAGE,
+                ));
+            }
+            if let Some(retry) = job.get("retry").and_then(Value::as_mapping) {
+                problems.extend(unknown_keys(
+                    source,
+                    &[section, name, "retry"],
+                    retry,
+                    keys::RETRY,
+                ));
+            }
+            for hook in ["before_script", "after_script"] {
+                if let Some(script) = job.get(hook).and_then(Value::as_mapping) {
+                    problems.extend(unknown_keys(
+                        source,
+                        &[section, name, hook],
+                        script,
+                        keys::HOOK,
+                    ));
+                }
+            }
+        }
+    }
+    for hook in ["setup", "teardown"] {
+        if let Some(script) = mapping(hook) {
+            problems.extend(unknown_keys(source, &[hook], script, keys::HOOK));
+        }
+    }
+    if let Some(constraints) = mapping("constraints") {
+        problems.extend(unknown_keys(
+            source,
+            &["constraints"],
+            constraints,
+            keys::CONSTRAINTS,
+        ));
+    }
+    if let Some(display) = mapping("display") {
+        problems.extend(unknown_keys(source, &["display"], display, keys::DISPLAY));
+        if let Some(spinner) = display.get("spinner").and_then(Value::as_mapping) {
+            problems.extend(unknown_keys(
+                source,
+                &["display", "spinner"],
+                spinner,
+                keys::SPINNER,
+            ));
+        }
+    }
+    problems
+}
+
+/// Parses a file, returning its problems along with its content when it loads
+fn parse(source: &Source) -> (Vec<Problem>, Option<(Mapping, Payload)>) {
+    let at = |error: &serde_yaml::Error| {
+        let location = error.location();
+        // the location is already part of the problem
+        let message = LOCATION.replace(&error.to_string(), "").to_string();
+        Problem {
+            file: source.file.clone(),
+            line: location.as_ref().map_or(1, serde_yaml::Location::line),
+            column: location.as_ref().map_or(1, serde_yaml::Location::column),
+            message,
+        }
+    };
+
+    let value: Value = match serde_yaml::from_str(&source.text) {
+        Ok(value) => value,
+        Err(error) => return (vec![at(&error)], None),
+    };
+    let Some(root) = value.as_mapping().cloned() else {
+        return (
+            vec![source.problem(&[], "expected a mapping".to_string())],
+            None,
+        );
+    };
+    let mut problems = schema(source, &root);
+
+    let mut payload = Payload::default();
+    match Config::parse(&source.text) {
+        Ok(loader) => {
+            if let Err(error) = loader.load(&mut payload) {
+                problems.push(match error {
+                    Error::Invalid(path, message) => {
+                        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+                        source.problem(&path, message)
+                    }
+                    error => source.problem(&[], error.explain(&source.file)),
+                });
+                return (problems, None);
+            }
+        }
+        Err(error) => {
+            problems.push(match serde_yaml::from_str::<Version1>(&source.text) {
+                Err(error) => at(&error),
+                Ok(_) => source.problem(&["version"], error.explain(&source.file)),
+            });
+            return (problems, None);
+        }
+    }
+    (problems, Some((root, payload)))
+}
+
+fn constraint_names(root: &Mapping) -> Vec<(&'static str, String, String)> {
+    let mut names = vec![];
+    let constraints = root.get("constraints").and_then(Value::as_mapping);
+    for kind in keys::CONSTRAINTS {
+        let entries = constraints
+            .and_then(|constraints| constraints.get(*kind))
+            .and_then(Value::as_mapping);
+        for (key, list) in entries.into_iter().flatten() {
+            let Some(key) = key.as_str() else {
+                continue;
+            };
+            names.push((*kind, key.to_string(), key.to_string()));
+            for name in list.as_sequence().into_iter().flatten() {
+                if let Some(name) = name.as_str() {
+                    names.push((*kind, key.to_string(), name.to_string()));
+                }
+            }
+        }
+    }
+    names
+}
+
+fn same_file(left: &str, right: &str) -> bool {
+    left == right
+        || fs::canonicalize(left)
+            .is_ok_and(|left| fs::canonicalize(right).is_ok_and(|right| left == right))
+}
+
+/// Reads and parses a config file, its problems go to `problems`
+fn read(file: String, problems: &mut Vec<Problem>) -> Option<(Source, Mapping, Payload)> {
+    let source = match fs::read_to_string(&file) {
+        Ok(text) => Source::new(file, text),
+        Err(error) => {
+            problems.push(Problem {
+                file,
+                line: 1,
+                column: 1,
+                message: format!("could not be read: {error}"),
+            });
+            return None;
+        }
+    };
+    let (found, content) = parse(&source);
+    problems.extend(found);
+    let (root, mut payload) = content?;
+    // like the loader, compare the env files relative to the file declaring them
+    for job in &mut payload.ci.jobs {
+        if let Some(path) = &mut job.env_file {
+            *path = Config::extra_path(&source.file, path);
+        }
+    }
+    Some((source, root, payload))
+}
+
+/// Checks the config file and its extra files, and returns all their problems
+pub fn check(config: &Config) -> Result<Vec<Problem>> {
+    let main = config.get_first_available_config_file()?;
+    let mut files = vec![main];
+    let mut problems = vec![];
+    let mut loaded: Vec<(Source, Mapping, Payload)> = vec![];
+
+    let mut index = 0;
+    while let Some(file) = files.get(index).cloned() {
+        index += 1;
+        if let Some((source, root, payload)) = read(file, &mut problems) {
+            for extra in &payload.extra_files {
+                let extra = Config::extra_path(&source.file, extra);
+                if !files.iter().any(|file| same_file(file, &extra)) {
+                    files.push(extra);
+                }
+            }
+            loaded.push((source, root, payload));
+        }
+    }
+
+    let groups: Vec<&String> = loaded
+        .iter()
+        .flat_map(|(_, _, payload)| &payload.ci.groups)
+        .collect();
+    let jobs: Vec<&str> = loaded
+        .iter()
+        .flat_map(|(_, _, payload)| {
+            let matrices = payload.ci.matrices.keys();
+            let names = payload.ci.jobs.iter().map(|job| &job.name);
+            names.chain(matrices).map(String::as_str)
+        })
+        .collect();
+
+    let mut defined: Vec<(&JobDesc, &str)> = vec![];
+    for (source, root, payload) in &loaded {
+        for job in &payload.ci.jobs {
+            let at = ["jobs", job.name.as_str()];
+            if let Some((known, first)) = defined
+                .iter()
+                .find(|(known, _)| known.name == job.name && *known != job)
+            {
+                problems.push(source.problem(
+                    &at,
+                    format!(
+                        "job {} is defined differently in {first}{}",
+                        job.name,
+                        known.conflict(job)
+                    ),
+                ));
+            }
+            defined.push((job, &source.file));
+
+            if job.script.is_empty() {
+                problems.push(source.problem(&at, format!("job {} has an empty script", job.name)));
+            }
+            for group in &job.group {
+                if !groups.contains(&group) {
+                    problems.push(source.problem(
+                        &["jobs", job.name.as_str(), "group"],
+                        format!(
+                            "group {group} of job {} is not declared in groups",
+                            job.name
+                        ),
+                    ));
+                }
+            }
+        }
+
+        for (kind, key, name) in constraint_names(root) {
+            if !jobs.contains(&name.as_str()) {
+                problems.push(source.problem(
+                    &["constraints", kind, &key],
+                    format!("{kind} names unknown job {name}"),
+                ));
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        let source = &loaded[0].0;
+        let mut payload = Payload::default();
+        if let Err(error) = config.load_into(&mut payload) {
+            problems.push(source.problem(&["extra_files"], error.to_string()));
+        } else if let Err(error) = Graph::new(&payload.ci) {
+            problems.push(source.problem(&["constraints"], error.to_string()));
+        }
+    }
+
+    problems.sort_by_key(|problem| {
+        let file = files.iter().position(|file| *file == problem.file);
+        (file, problem.line, problem.column)
+    });
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::job::tests::temp_path;
+
+    #[test]
+    pub fn locations() {
+        let source = |text: &str| Source::new("qad.yaml".to_string(), text.to_string());
+        let at = |source: &Source, path: &[&str]| {
+            let problem = source.problem(path, String::new());
+            (problem.line, problem.column)
+        };
+
+        let block = source(
+            "version: 1\njobs:\n  # comment\n  fmt:\n    script: []\n  tests:\n    group: test\n",
+        );
+        assert_eq!((4, 3), at(&block, &["jobs", "fmt"]));
+        assert_eq!((7, 5), at(&block, &["jobs", "tests", "group"]));
+        assert_eq!((6, 3), at(&block, &["jobs", "tests", "image"]));
+        assert_eq!((1, 1), at(&block, &["constraints"]));
+
+        let flow = source("version: 1\njobs: {fmt: {script: [cargo fmt]}, tests: {group: test}}\n");
+        assert_eq!((2, 8), at(&flow, &["jobs", "fmt"]));
+        assert_eq!((2, 44), at(&flow, &["jobs", "tests", "group"]));
+
+        let tricky = source(
+            "version: 1\n\"a:b\": 1\njobs:\n  fmt:\n    script:\n      - |\n        group: test\n    group: check\n",
+        );
+        assert_eq!((2, 1), at(&tricky, &["a:b"]));
+        assert_eq!((8, 5), at(&tricky, &["jobs", "fmt", "group"]));
+    }
+
+    #[test]
+    pub fn all_problems_at_once() {
+        let dir = temp_path("check");
+        fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("qad.yaml").display().to_string();
+        let extra = dir.join("extra.yaml").display().to_string();
+        fs::write(
+            &main,
+            format!(
+                "version: \"1\"\n\
+                 groups: [check]\n\
+                 extra_files: [{extra}]\n\
+                 jobs:\n  \
+                   fmt:\n    \
+                     script: [cargo fmt]\n    \
+                     group: chek\n  \
+                   tests:\n    \
+                     script: []\n\
+                 contraints:\n  \
+                   blocks:\n    \
+                     fmt: [tests]\n"
+            ),
+        )
+        .unwrap();
+        fs::write(
+            &extra,
+            "version: \"1\"\n\
+             jobs:\n  \
+               tests:\n    \
+                 script: [cargo test]\n\
+             constraints:\n  \
+               needs:\n    \
+                 deploy: [tests]\n",
+        )
+        .unwrap();
+
+        let problems: Vec<String> = check(&Config::from_name(&main))
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            vec![
+                format!("{main}:7:5: group chek of job fmt is not declared in groups"),
+                format!("{main}:8:3: job tests has an empty script"),
+                format!("{main}:10:1: unknown key contraints (did you mean constraints?)"),
+                format!("{extra}:3:3: job tests is defined differently in {main}"),
+                format!("{extra}:7:5: needs names unknown job deploy"),
+            ],
+            problems
+        );
+    }
+
+    #[test]
+    pub fn env_files_are_compared_where_they_resolve() {
+        let dir = temp_path("check-env-file");
+        fs::create_dir_all(dir.join("ci")).unwrap();
+        let main = dir.join("qad.yaml").display().to_string();
+        fs::write(
+            &main,
+            "version: \"1\"\n\
+             extra_files: [ci/test.yaml]\n\
+             jobs:\n  \
+               tests:\n    \
+                 script: [cargo test]\n    \
+                 env_file: ci/x.env\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("ci/test.yaml"),
+            "version: \"1\"\n\
+             jobs:\n  \
+               tests:\n    \
+                 script: [cargo test]\n    \
+                 env_file: x.env\n",
+        )
+        .unwrap();
+
+        let problems = check(&Config::from_name(&main)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(Vec::<Problem>::new(), problems);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod argh;
+pub mod check;
 pub mod migrate;
 mod versions;
 pub mod yaml_parser;
@@ -9,8 +10,9 @@ use anyhow::Error as AnyError;
 use anyhow::Result;
 use regex::Regex;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use yaml_parser::YamlParser;
 
 #[derive(Debug)]
@@ -19,6 +21,8 @@ pub enum Error {
     NoVersion(&'static str, String),
     BadVersion(String, &'static str),
     ContentParse(String, String),
+    /// The value at the given keys is invalid
+    Invalid(Vec<String>, String),
 }
 
 impl Error {
@@ -33,10 +37,13 @@ impl Error {
                 )
             }
             Error::BadVersion(version, latest) => {
-                format!("unknown version {version} in {filename} (latest is {latest})",)
+                format!("unknown version {version} in {filename} (latest is {latest})")
             }
             Error::ContentParse(version, prev) => {
-                format!("could not parse {filename} with version {version} ({prev})",)
+                format!("could not parse {filename} with version {version} ({prev})")
+            }
+            Error::Invalid(path, message) => {
+                format!("invalid {} in {filename}: {message}", path.join("."))
             }
         }
     }
@@ -57,20 +64,84 @@ pub struct Payload {
     pub display: CiDisplayConfig,
     pub env: Option<String>,
     pub extra_files: Vec<String>,
+    /// The file defining each job
+    pub job_files: HashMap<String, String>,
+    /// The keys set by the file which only the main file may set
+    pub main_file_keys: Vec<&'static str>,
 }
 
 impl Payload {
-    pub fn absorb(&mut self, other: &Payload) {
+    /// Takes the jobs, constraints, groups and env of an extra file.
+    /// Groups and env it adds come after the known ones
+    pub fn absorb(&mut self, other: &Payload, filename: &str) -> Result<()> {
+        if let Some(key) = other.main_file_keys.first() {
+            return Err(AnyError::msg(format!(
+                "{key} is set in the extra file {filename}, only the main file may set it"
+            )));
+        }
+        let origin = |job: &str| other.job_files.get(job).map_or(filename, String::as_str);
         for new_job in &other.ci.jobs {
-            if !self.ci.jobs.contains(new_job) {
-                self.ci.jobs.push(new_job.clone());
+            match self.ci.jobs.iter().find(|job| job.name == new_job.name) {
+                None => {
+                    self.ci.jobs.push(new_job.clone());
+                    self.job_files
+                        .insert(new_job.name.clone(), origin(&new_job.name).to_string());
+                }
+                Some(job) if job == new_job => {}
+                Some(job) => {
+                    return Err(AnyError::msg(format!(
+                        "job {} is defined differently in {} and {}{}",
+                        job.name,
+                        self.job_files.get(&job.name).map_or("?", String::as_str),
+                        origin(&new_job.name),
+                        job.conflict(new_job)
+                    )));
+                }
+            }
+        }
+        for constraint in &other.ci.constraints {
+            if !self.ci.constraints.contains(constraint) {
+                self.ci.constraints.push(constraint.clone());
+                let origin = other
+                    .ci
+                    .origins
+                    .get(constraint)
+                    .map_or(filename, String::as_str);
+                self.ci
+                    .origins
+                    .insert(constraint.clone(), origin.to_string());
+            }
+        }
+        for (name, cells) in &other.ci.matrices {
+            self.ci
+                .matrices
+                .entry(name.clone())
+                .or_insert_with(|| cells.clone());
+        }
+        for group in &other.ci.groups {
+            if !self.ci.groups.contains(group) {
+                self.ci.groups.push(group.clone());
+            }
+        }
+        for secret in &other.ci.secrets {
+            if !self.ci.secrets.contains(secret) {
+                self.ci.secrets.push(secret.clone());
             }
         }
+        self.env = join_env(self.env.take(), other.env.clone());
+        Ok(())
+    }
+}
+
+fn join_env(first: Option<String>, then: Option<String>) -> Option<String> {
+    match (first, then) {
+        (Some(first), Some(then)) => Some(format!("{first}\n{then}")),
+        (first, then) => first.or(then),
     }
 }
 
 pub trait Loader {
-    fn load(&self, payload: &mut Payload);
+    fn load(&self, payload: &mut Payload) -> Result<(), Error>;
 }
 
 pub trait FormatParser {
@@ -121,13 +192,65 @@ impl Config {
     pub fn load_into(&self, config: &mut Payload) -> Result<()> {
         let filename = self.get_first_available_config_file()?;
 
-        Self::load_unknown_file(config, &filename)?;
+        Self::load_file(config, &filename, &mut vec![], &mut vec![])?;
+        config.ci.expand_constraints();
+        Ok(())
+    }
+
+    /// Path of an extra file, relative to the file including it
+    pub fn extra_path(including: &str, extra: &str) -> String {
+        Path::new(including)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(extra)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Loads a file then its extra files, which may include other ones.
+    /// `chain` holds the files including this one, `loaded` all the files loaded so far
+    fn load_file(
+        config: &mut Payload,
+        filename: &str,
+        chain: &mut Vec<(PathBuf, String)>,
+        loaded: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let identity = fs::canonicalize(filename).unwrap_or_else(|_| PathBuf::from(filename));
+        if chain.iter().any(|(file, _)| *file == identity) {
+            let mut names: Vec<&str> = chain.iter().map(|(_, name)| name.as_str()).collect();
+            names.push(filename);
+            return Err(AnyError::msg(format!(
+                "extra_files include each other: {}",
+                names.join(" -> ")
+            )));
+        }
+        if loaded.contains(&identity) {
+            return Ok(());
+        }
+        loaded.push(identity.clone());
+
+        Self::load_unknown_file(config, filename)?;
+        for job in &mut config.ci.jobs {
+            config
+                .job_files
+                .insert(job.name.clone(), filename.to_string());
+            // like extra files, env files are relative to the file declaring them
+            if let Some(path) = &mut job.env_file {
+                *path = Self::extra_path(filename, path);
+            }
+        }
 
-        for file in config.extra_files.clone() {
+        // the env of the extra files runs first, so that the including file overrides it
+        let env = config.env.take();
+        chain.push((identity, filename.to_string()));
+        for extra in config.extra_files.clone() {
+            let file = Self::extra_path(filename, &extra);
             let mut other = Payload::default();
-            Self::load_unknown_file(&mut other, &file)?;
-            config.absorb(&other);
+            Self::load_file(&mut other, &file, chain, loaded)?;
+            config.absorb(&other, &file)?;
         }
+        chain.pop();
+        config.env = join_env(config.env.take(), env);
 
         Ok(())
     }
@@ -139,7 +262,9 @@ impl Config {
 
         let loader =
             Self::parse(&content).map_err(|error| AnyError::msg(error.explain(filename)))?;
-        loader.load(config);
+        loader
+            .load(config)
+            .map_err(|error| AnyError::msg(error.explain(filename)))?;
 
         Ok(())
     }
@@ -180,3 +305,190 @@ impl Config {
         Ok(ver)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::job::tests::temp_path;
+    use crate::strvec;
+
+    #[test]
+    pub fn extra_files_are_merged() {
+        let dir = temp_path("extra");
+        fs::create_dir_all(dir.join("ci")).unwrap();
+        let write = |file: &str, content: &str| fs::write(dir.join(file), content).unwrap();
+        write(
+            "qad.yaml",
+            "version: \"1\"\ngroups: [check]\nextra_files: [ci/test.yaml]\nenv: echo MAIN=1\n\
+             jobs:\n  fmt:\n    script: [cargo fmt]\n    group: check\n",
+        );
+        write(
+            "ci/test.yaml",
+            "version: \"1\"\ngroups: [test, check]\nextra_files: [deploy.yaml]\nenv: echo TEST=1\n\
+             jobs:\n  tests:\n    script: [cargo test]\n    group: test\n\
+             constraints:\n  needs:\n    tests: [deploy]\n",
+        );
+        write(
+            "ci/deploy.yaml",
+            "version: \"1\"\nenv: echo DEPLOY=1\njobs:\n  deploy:\n    script: [make]\n  \
+             fmt:\n    script: [cargo fmt]\n    group: check\n",
+        );
+
+        let main = dir.join("qad.yaml").display().to_string();
+        let mut payload = Payload::default();
+        Config::from_name(&main).load_into(&mut payload).unwrap();
+
+        let jobs: Vec<&str> = payload
+            .ci
+            .jobs
+            .iter()
+            .map(|job| job.name.as_str())
+            .collect();
+        assert_eq!(vec!["fmt", "tests", "deploy"], jobs);
+        assert_eq!(strvec!("check", "test"), payload.ci.groups);
+        assert_eq!(
+            Some(&dir.join("ci/test.yaml").display().to_string()),
+            payload
+                .ci
+                .origins
+                .get(&("deploy".to_string(), "tests".to_string()))
+        );
+        assert_eq!(
+            Some("echo DEPLOY=1\necho TEST=1\necho MAIN=1".to_string()),
+            payload.env
+        );
+
+        write(
+            "ci/deploy.yaml",
+            "version: \"1\"\nextra_files: [../qad.yaml]\njobs:\n  fmt:\n    script: [cargo fmt --check]\n",
+        );
+        let error = Config::from_name(&main)
+            .load_into(&mut Payload::default())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            error.starts_with("extra_files include each other: "),
+            "{error}"
+        );
+
+        write(
+            "ci/deploy.yaml",
+            "version: \"1\"\ndisplay:\n  mode: summary\njobs:\n  deploy:\n    script: [make]\n",
+        );
+        let error = Config::from_name(&main)
+            .load_into(&mut Payload::default())
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            format!(
+                "display is set in the extra file {}, only the main file may set it",
+                dir.join("ci/deploy.yaml").display()
+            ),
+            error
+        );
+
+        write(
+            "ci/deploy.yaml",
+            "version: \"1\"\nteardown: [make clean]\njobs:\n  deploy:\n    script: [make]\n",
+        );
+        let error = Config::from_name(&main)
+            .load_into(&mut Payload::default())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            error.starts_with("teardown is set in the extra file "),
+            "{error}"
+        );
+
+        write(
+            "ci/deploy.yaml",
+            "version: \"1\"\njobs:\n  fmt:\n    script: [cargo fmt --check]\n",
+        );
+        let error = Config::from_name(&main)
+            .load_into(&mut Payload::default())
+            .err()
+            .unwrap()
+            .to_string();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            format!(
+                "job fmt is defined differently in {main} and {}",
+                dir.join("ci/deploy.yaml").display()
+            ),
+            error
+        );
+    }
+
+    #[test]
+    pub fn env_file_conflicts_are_explained() {
+        let dir = temp_path("env-conflict");
+        fs::create_dir_all(dir.join("ci")).unwrap();
+        fs::create_dir_all(dir.join("deploy")).unwrap();
+        let write = |file: &str, content: &str| fs::write(dir.join(file), content).unwrap();
+        let job = "version: \"1\"\njobs:\n  tests:\n    script: [cargo test]\n    env_file: .env\n";
+        write(
+            "qad.yaml",
+            "version: \"1\"\nextra_files: [ci/test.yaml, deploy/test.yaml]\njobs: {}\n",
+        );
+        write("ci/test.yaml", job);
+        write("deploy/test.yaml", job);
+
+        let error = Config::from_name(&dir.join("qad.yaml").display().to_string())
+            .load_into(&mut Payload::default())
+            .err()
+            .unwrap()
+            .to_string();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let path = |file: &str| dir.join(file).display().to_string();
+        assert_eq!(
+            format!(
+                "job tests is defined differently in {} and {}: their env_file resolve to {} and {}",
+                path("ci/test.yaml"),
+                path("deploy/test.yaml"),
+                path("ci/.env"),
+                path("deploy/.env")
+            ),
+            error
+        );
+    }
+
+    #[test]
+    pub fn env_files_are_relative_to_their_config_file() {
+        let dir = temp_path("env-file");
+        fs::create_dir_all(dir.join("ci")).unwrap();
+        let write = |file: &str, content: &str| fs::write(dir.join(file), content).unwrap();
+        write(
+            "qad.yaml",
+            "version: \"1\"\nextra_files: [ci/test.yaml]\n\
+             jobs:\n  fmt:\n    script: [cargo fmt]\n    env_file: .env\n",
+        );
+        write(
+            "ci/test.yaml",
+            "version: \"1\"\njobs:\n  tests:\n    script: [cargo test]\n    env_file: .env\n",
+        );
+
+        let mut payload = Payload::default();
+        Config::from_name(&dir.join("qad.yaml").display().to_string())
+            .load_into(&mut payload)
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let env_files: Vec<String> = payload
+            .ci
+            .jobs
+            .iter()
+            .filter_map(|job| job.env_file.clone())
+            .collect();
+        assert_eq!(
+            vec![
+                dir.join(".env").display().to_string(),
+                dir.join("ci/.env").display().to_string()
+            ],
+            env_files
+        );
+    }
+}
@@ -1,22 +1,46 @@
 use crate::ci::config::JobDesc;
-use crate::ci::display::FinalDisplayMode;
 use crate::ci::display::Running as RunningDisplay;
-use crate::ci::job::container_configuration::DockerContainer;
-use crate::config::{Loader, Payload};
+use crate::ci::display::{CiDisplayConfig, FinalDisplayMode};
+use crate::ci::job::container_configuration::{DockerContainer, Lifetime};
+use crate::ci::job::{Failure, HookScript, Retry};
+use crate::config::{Error, Loader, Payload};
+use indexmap::IndexMap;
+use regex::{Captures, Regex};
 use serde::de::{MapAccess, Visitor};
 use serde::{de, Deserialize, Deserializer};
 use std::collections::HashMap;
 use std::fmt;
 use std::marker::PhantomData;
 use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::Duration;
 
+/// How long the container of a job lives
+#[derive(Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ContainerLifetime {
+    Instruction,
+    Job,
+}
+
+impl From<ContainerLifetime> for Lifetime {
+    fn from(lifetime: ContainerLifetime) -> Self {
+        match lifetime {
+            ContainerLifetime::Instruction => Self::Instruction,
+            ContainerLifetime::Job => Self::Job,
+        }
+    }
+}
+
+/// The fields left unset by the job and its templates take the values of the name alone
 #[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
 struct ContainerReference {
-    image: String,
-    env: Vec<String>,
-    volumes: Vec<String>,
-    user: String,
-    workdir: String,
+    image: Option<String>,
+    env: Option<Vec<String>>,
+    volumes: Option<Vec<String>>,
+    user: Option<String>,
+    workdir: Option<String>,
+    lifetime: Option<ContainerLifetime>,
 }
 
 impl FromStr for ContainerReference {
@@ -24,11 +48,12 @@ impl FromStr for ContainerReference {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(Self {
-            image: s.to_string(),
-            env: Default::default(),
-            volumes: vec!["$PWD:$PWD:rw".to_string()],
-            user: "$USER_ID:$GROUP_ID".to_string(),
-            workdir: "$PWD".to_string(),
+            image: Some(s.to_string()),
+            env: None,
+            volumes: None,
+            user: None,
+            workdir: None,
+            lifetime: None,
         })
     }
 }
@@ -77,16 +102,330 @@ where
     deserializer.deserialize_any(StringOrStruct(PhantomData))
 }
 
+/// Hours, minutes then seconds, each one optional
+static DURATION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(?:(\d+)h)?\s*(?:(\d+)m)?\s*(?:(\d+)s?)?\s*$").unwrap());
+
+/// Durations are either a number of seconds or a string like `1h30m`, `5m` or `90s`
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let error = || format!("invalid duration {text:?} (expected e.g. 90, 90s, 5m or 1h30m)");
+    let captures = DURATION.captures(text).ok_or_else(error)?;
+
+    let mut seconds = None;
+    for (index, factor) in [(1, 3600), (2, 60), (3, 1)] {
+        if let Some(value) = captures.get(index) {
+            let value: u64 = value.as_str().parse().map_err(|_| error())?;
+            seconds = Some(
+                value
+                    .checked_mul(factor)
+                    .and_then(|value| value.checked_add(seconds.unwrap_or(0)))
+                    .ok_or_else(|| format!("duration {text:?} is too long"))?,
+            );
+        }
+    }
+
+    seconds.map(Duration::from_secs).ok_or_else(error)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DurationRepr {
+    Seconds(u64),
+    Text(String),
+}
+
+fn optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<DurationRepr>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(DurationRepr::Seconds(seconds)) => Ok(Some(Duration::from_secs(seconds))),
+        Some(DurationRepr::Text(text)) => {
+            parse_duration(&text).map(Some).map_err(de::Error::custom)
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
 struct ContainerWrapper(#[serde(deserialize_with = "string_or_struct")] ContainerReference);
 
+impl ContainerReference {
+    /// The fields set in `other` replace the ones of `self`
+    fn overridden_by(self, other: Self) -> Self {
+        Self {
+            image: other.image.or(self.image),
+            env: other.env.or(self.env),
+            volumes: other.volumes.or(self.volumes),
+            user: other.user.or(self.user),
+            workdir: other.workdir.or(self.workdir),
+            lifetime: other.lifetime.or(self.lifetime),
+        }
+    }
+
+    /// The container, with the matrix variables of `cell` substituted
+    fn container(&self, cell: &[(String, String)]) -> Result<DockerContainer, String> {
+        let one = |text: Option<&String>, default: &str| {
+            substitute(text.map_or(default, String::as_str), cell)
+        };
+        let all = |texts: Option<&Vec<String>>, default: &[&str]| {
+            texts
+                .map_or_else(
+                    || default.iter().map(ToString::to_string).collect(),
+                    Clone::clone,
+                )
+                .iter()
+                .map(|text| substitute(text, cell))
+                .collect::<Result<Vec<String>, String>>()
+        };
+        let image = self.image.as_ref().ok_or_else(|| {
+            "missing field `image.image`, in the job or the templates it extends".to_string()
+        })?;
+        Ok(DockerContainer::new(
+            &substitute(image, cell)?,
+            &one(self.user.as_ref(), "$USER_ID:$GROUP_ID")?,
+            &one(self.workdir.as_ref(), "$PWD")?,
+            &all(self.volumes.as_ref(), &["$PWD:$PWD:rw"])?,
+            &all(self.env.as_ref(), &[])?,
+        )
+        .with_lifetime(self.lifetime.map(Into::into).unwrap_or_default()))
+    }
+}
+
+/// A value of a matrix or of a variable, written as a string, a number or a boolean
+#[derive(Debug, Eq, PartialEq, Clone)]
+struct Scalar(String);
+
+impl<'de> Deserialize<'de> for Scalar {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match serde_yaml::Value::deserialize(deserializer)? {
+            serde_yaml::Value::String(text) => Ok(Self(text)),
+            // 1.80 would read as 1.8
+            serde_yaml::Value::Number(number) if number.is_f64() => Err(de::Error::custom(
+                format!("quote the value {number} to keep it as written"),
+            )),
+            serde_yaml::Value::Number(number) => Ok(Self(number.to_string())),
+            serde_yaml::Value::Bool(bool) => Ok(Self(bool.to_string())),
+            _ => Err(de::Error::custom(
+                "expected a string, a number or a boolean",
+            )),
+        }
+    }
+}
+
+/// The values of each variable of a matrix job, in their order of declaration
+type Matrix = IndexMap<String, Vec<Scalar>>;
+
+/// Every combination of the values of the matrix, as the variables of each cell
+fn cells(matrix: &Matrix) -> Vec<Vec<(String, String)>> {
+    matrix
+        .iter()
+        .fold(vec![vec![]], |cells, (variable, values)| {
+            cells
+                .iter()
+                .flat_map(|cell| {
+                    values.iter().map(move |value| {
+                        let mut cell = cell.clone();
+                        cell.push((variable.clone(), value.0.clone()));
+                        cell
+                    })
+                })
+                .collect()
+        })
+}
+
+/// A `${{ matrix.variable }}` placeholder
+static MATRIX_VARIABLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$\{\{\s*matrix\.(\w+)\s*\}\}").unwrap());
+
+/// Replaces each `${{ matrix.variable }}` with its value in the cell
+fn substitute(text: &str, cell: &[(String, String)]) -> Result<String, String> {
+    let mut unknown = None;
+    let substituted = MATRIX_VARIABLE.replace_all(text, |captures: &Captures| {
+        let name = &captures[1];
+        cell.iter()
+            .find(|(variable, _)| variable == name)
+            .map_or_else(
+                || {
+                    unknown = Some(name.to_string());
+                    String::new()
+                },
+                |(_, value)| value.clone(),
+            )
+    });
+    match unknown {
+        None => Ok(substituted.to_string()),
+        Some(name) => Err(format!("unknown matrix variable {name}")),
+    }
+}
+
+/// The failures an instruction runs again on
+#[derive(Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum RetryWhen {
+    JobError,
+    ProcessError,
+    TimedOut,
+}
+
+impl From<RetryWhen> for Failure {
+    fn from(when: RetryWhen) -> Self {
+        match when {
+            RetryWhen::JobError => Self::JobError,
+            RetryWhen::ProcessError => Self::ProcessError,
+            RetryWhen::TimedOut => Self::TimedOut,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+struct RetryDesc {
+    max: Option<u32>,
+    /// Every failure when unset
+    when: Option<Vec<RetryWhen>>,
+}
+
+impl RetryDesc {
+    /// The fields set in `other` replace the ones of `self`
+    fn overridden_by(self, other: Self) -> Self {
+        Self {
+            max: other.max.or(self.max),
+            when: other.when.or(self.when),
+        }
+    }
+
+    fn retry(&self) -> Result<Retry, String> {
+        Ok(Retry {
+            max: self.max.ok_or_else(|| {
+                "missing field `retry.max`, in the job or the templates it extends".to_string()
+            })?,
+            when: self.when.as_ref().map_or_else(
+                || vec![Failure::JobError, Failure::ProcessError, Failure::TimedOut],
+                |when| when.iter().copied().map(Failure::from).collect(),
+            ),
+        })
+    }
+}
+
+/// A hook, either its instructions or a mapping telling whether its failure is fatal
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[serde(untagged)]
+enum HookDesc {
+    Script(Vec<String>),
+    Full {
+        script: Vec<String>,
+        fatal: Option<bool>,
+    },
+}
+
+impl HookDesc {
+    /// The hook, with the matrix variables of `cell` substituted
+    fn script(
+        hook: Option<&Self>,
+        fatal_by_default: bool,
+        cell: &[(String, String)],
+    ) -> Result<HookScript, String> {
+        let (instructions, fatal) = match hook {
+            None => return Ok(HookScript::none()),
+            Some(Self::Script(script)) => (script, None),
+            Some(Self::Full { script, fatal }) => (script, *fatal),
+        };
+        Ok(HookScript {
+            instructions: instructions
+                .iter()
+                .map(|instruction| substitute(instruction, cell))
+                .collect::<Result<Vec<String>, String>>()?,
+            fatal: fatal.unwrap_or(fatal_by_default),
+        })
+    }
+}
+
 #[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[serde(untagged)]
+enum Extends {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// A job, or a template when it is listed in `templates`.
+/// Unset fields are taken from the templates the job extends
+#[derive(Deserialize, Debug, Default, Eq, PartialEq, Clone)]
 pub struct FullJobDesc {
-    script: Vec<String>,
+    script: Option<Vec<String>>,
     #[serde(rename = "image")]
     container_reference: Option<ContainerWrapper>,
     group: Option<String>,
     skip_if: Option<String>,
+    #[serde(default, deserialize_with = "optional_duration")]
+    timeout: Option<Duration>,
+    allow_failure: Option<bool>,
+    retry: Option<RetryDesc>,
+    /// Fatal by default
+    before_script: Option<HookDesc>,
+    /// Not fatal by default
+    after_script: Option<HookDesc>,
+    inputs: Option<Vec<String>>,
+    watch: Option<Vec<String>>,
+    /// Set in the environment of the job, over the global `env`
+    variables: Option<IndexMap<String, Scalar>>,
+    /// Dotenv file read before each run, `variables` override its values
+    env_file: Option<String>,
+    matrix: Option<Matrix>,
+    extends: Option<Extends>,
+}
+
+impl FullJobDesc {
+    fn extends(&self) -> Vec<String> {
+        match &self.extends {
+            None => vec![],
+            Some(Extends::One(template)) => vec![template.clone()],
+            Some(Extends::Many(templates)) => templates.clone(),
+        }
+    }
+
+    /// The fields set in `other` replace the ones of `self`
+    fn overridden_by(self, other: Self) -> Self {
+        Self {
+            script: other.script.or(self.script),
+            container_reference: merged(
+                self.container_reference,
+                other.container_reference,
+                |reference, overrides| ContainerWrapper(reference.0.overridden_by(overrides.0)),
+            ),
+            group: other.group.or(self.group),
+            skip_if: other.skip_if.or(self.skip_if),
+            timeout: other.timeout.or(self.timeout),
+            allow_failure: other.allow_failure.or(self.allow_failure),
+            retry: merged(self.retry, other.retry, RetryDesc::overridden_by),
+            before_script: other.before_script.or(self.before_script),
+            after_script: other.after_script.or(self.after_script),
+            inputs: other.inputs.or(self.inputs),
+            watch: other.watch.or(self.watch),
+            // a job adds to the variables of its templates
+            variables: merged(
+                self.variables,
+                other.variables,
+                |mut variables, overrides| {
+                    variables.extend(overrides);
+                    variables
+                },
+            ),
+            env_file: other.env_file.or(self.env_file),
+            matrix: other.matrix.or(self.matrix),
+            extends: None,
+        }
+    }
+}
+
+/// `overrides` if only it is set, the merge of both if they are
+fn merged<T>(value: Option<T>, overrides: Option<T>, merge: impl FnOnce(T, T) -> T) -> Option<T> {
+    match (value, overrides) {
+        (Some(value), Some(overrides)) => Some(merge(value, overrides)),
+        (value, overrides) => overrides.or(value),
+    }
 }
 
 pub type JobSet = HashMap<String, FullJobDesc>;
@@ -137,6 +476,7 @@ pub enum FinalDisplay {
     Full,
     Silent,
     Interactive,
+    Junit,
 }
 
 impl From<FinalDisplayMode> for FinalDisplay {
@@ -145,6 +485,7 @@ impl From<FinalDisplayMode> for FinalDisplay {
             FinalDisplayMode::Full => Self::Full,
             FinalDisplayMode::Silent => Self::Silent,
             FinalDisplayMode::Interactive => Self::Interactive,
+            FinalDisplayMode::Junit => Self::Junit,
         }
     }
 }
@@ -154,6 +495,7 @@ impl From<FinalDisplay> for FinalDisplayMode {
             FinalDisplay::Full => Self::Full,
             FinalDisplay::Silent => Self::Silent,
             FinalDisplay::Interactive => Self::Interactive,
+            FinalDisplay::Junit => Self::Junit,
         }
     }
 }
@@ -165,49 +507,290 @@ struct Display {
     final_item: Option<FinalDisplay>,
     ok: Option<String>,
     ko: Option<String>,
+    warn: Option<String>,
     cancelled: Option<String>,
+    timed_out: Option<String>,
+    junit_file: Option<String>,
     spinner: Option<Spinner>,
 }
 
+impl Display {
+    fn apply(&self, config: &mut CiDisplayConfig) {
+        if let Some(ok) = &self.ok {
+            config.ok.clone_from(ok);
+        }
+        if let Some(ko) = &self.ko {
+            config.ko.clone_from(ko);
+        }
+        if let Some(warn) = &self.warn {
+            config.warn.clone_from(warn);
+        }
+        if let Some(cancelled) = &self.cancelled {
+            config.cancelled.clone_from(cancelled);
+        }
+        if let Some(timed_out) = &self.timed_out {
+            config.timed_out.clone_from(timed_out);
+        }
+        if let Some(junit_file) = &self.junit_file {
+            config.junit_file.clone_from(junit_file);
+        }
+        if let Some(spinner) = &self.spinner {
+            config.spinner = (spinner.frames.clone(), spinner.per_frames);
+        }
+        if let Some(mode) = &self.mode {
+            config.running_display = RunningDisplay::from(*mode);
+        }
+        if let Some(final_display) = &self.final_item {
+            config.final_display = FinalDisplayMode::from(*final_display);
+        }
+    }
+}
+
+/// The keys each mapping of the format accepts, which `qad check` validates
+pub mod keys {
+    pub const ROOT: &[&str] = &[
+        "version",
+        "jobs",
+        "groups",
+        "constraints",
+        "display",
+        "env",
+        "secrets",
+        "extra_files",
+        "concurrency",
+        "timeout",
+        "keep_going",
+        "setup",
+        "teardown",
+        "templates",
+    ];
+    pub const JOB: &[&str] = &[
+        "script",
+        "image",
+        "group",
+        "skip_if",
+        "timeout",
+        "allow_failure",
+        "retry",
+        "before_script",
+        "after_script",
+        "inputs",
+        "watch",
+        "variables",
+        "env_file",
+        "matrix",
+        "extends",
+    ];
+    pub const RETRY: &[&str] = &["max", "when"];
+    pub const HOOK: &[&str] = &["script", "fatal"];
+    pub const IMAGE: &[&str] = &["image", "env", "volumes", "user", "workdir", "lifetime"];
+    pub const CONSTRAINTS: &[&str] = &["blocks", "needs"];
+    pub const DISPLAY: &[&str] = &[
+        "mode",
+        "final",
+        "ok",
+        "ko",
+        "warn",
+        "cancelled",
+        "timed_out",
+        "junit_file",
+        "spinner",
+    ];
+    pub const SPINNER: &[&str] = &["frames", "per_frames"];
+}
+
 #[derive(Deserialize, Debug, Eq, PartialEq)]
 pub struct Version1 {
     version: String,
     jobs: JobSet,
+    #[serde(default)]
+    templates: JobSet,
     groups: Option<Vec<String>>,
     constraints: Option<Constraints>,
     display: Option<Display>,
     env: Option<String>,
+    secrets: Option<Vec<String>>,
     extra_files: Option<Vec<String>>,
+    concurrency: Option<usize>,
+    #[serde(default, deserialize_with = "optional_duration")]
+    timeout: Option<Duration>,
+    keep_going: Option<bool>,
+    setup: Option<HookDesc>,
+    teardown: Option<HookDesc>,
+}
+
+impl Version1 {
+    /// Merges the templates a job or template extends, in order, then its own fields.
+    /// `chain` holds the templates being resolved, to catch the ones extending each other
+    fn resolve(
+        &self,
+        section: &str,
+        name: &str,
+        desc: &FullJobDesc,
+        chain: &mut Vec<String>,
+    ) -> Result<FullJobDesc, Error> {
+        let invalid = |message: String| {
+            Error::Invalid(
+                vec![section.to_string(), name.to_string(), "extends".to_string()],
+                message,
+            )
+        };
+
+        let mut resolved = FullJobDesc::default();
+        for template in desc.extends() {
+            if chain.contains(&template) {
+                chain.push(template);
+                return Err(invalid(format!(
+                    "templates extend each other: {}",
+                    chain.join(" -> ")
+                )));
+            }
+            let Some(parent) = self.templates.get(&template) else {
+                return Err(invalid(format!("unknown template {template}")));
+            };
+            chain.push(template.clone());
+            let parent = self.resolve("templates", &template, parent, chain)?;
+            chain.pop();
+            resolved = resolved.overridden_by(parent);
+        }
+        Ok(resolved.overridden_by(desc.clone()))
+    }
+
+    /// The jobs a job stands for, one per cell of its matrix
+    fn expand(name: &str, desc: &FullJobDesc) -> Result<Vec<JobDesc>, String> {
+        let cells = match &desc.matrix {
+            None => vec![vec![]],
+            Some(matrix) => {
+                if let Some((variable, _)) = matrix.iter().find(|(_, values)| values.is_empty()) {
+                    return Err(format!("matrix variable {variable} has no value"));
+                }
+                cells(matrix)
+            }
+        };
+
+        cells
+            .iter()
+            .map(|cell| {
+                let name = if desc.matrix.is_some() {
+                    let values: Vec<&str> = cell.iter().map(|(_, value)| value.as_str()).collect();
+                    format!("{name}[{}]", values.join(","))
+                } else {
+                    name.to_string()
+                };
+                let image = desc
+                    .container_reference
+                    .as_ref()
+                    .map(|wrapper| wrapper.0.container(cell))
+                    .transpose()?;
+                Ok(JobDesc {
+                    name,
+                    script: desc
+                        .script
+                        .iter()
+                        .flatten()
+                        .map(|instruction| substitute(instruction, cell))
+                        .collect::<Result<Vec<String>, String>>()?,
+                    image,
+                    group: desc.group.iter().cloned().collect::<Vec<String>>(),
+                    skip_if: desc
+                        .skip_if
+                        .as_ref()
+                        .map(|condition| substitute(condition, cell))
+                        .transpose()?,
+                    timeout: desc.timeout,
+                    allow_failure: desc.allow_failure.unwrap_or_default(),
+                    retry: desc
+                        .retry
+                        .as_ref()
+                        .map(RetryDesc::retry)
+                        .transpose()?
+                        .unwrap_or_default(),
+                    before_script: HookDesc::script(desc.before_script.as_ref(), true, cell)?,
+                    after_script: HookDesc::script(desc.after_script.as_ref(), false, cell)?,
+                    inputs: desc.inputs.clone().unwrap_or_default(),
+                    watch: desc.watch.clone().unwrap_or_default(),
+                    variables: desc
+                        .variables
+                        .iter()
+                        .flatten()
+                        .map(|(key, value)| Ok((key.clone(), substitute(&value.0, cell)?)))
+                        .collect::<Result<Vec<(String, String)>, String>>()?,
+                    env_file: desc
+                        .env_file
+                        .as_ref()
+                        .map(|path| substitute(path, cell))
+                        .transpose()?,
+                })
+            })
+            .collect()
+    }
 }
 
 impl Loader for Version1 {
-    fn load(&self, payload: &mut Payload) {
+    fn load(&self, payload: &mut Payload) -> Result<(), Error> {
         for (name, full_desc) in self.jobs.clone() {
-            let image =
-                full_desc
-                    .container_reference
-                    .map(|container_reference: ContainerWrapper| {
-                        DockerContainer::new(
-                            &container_reference.0.image,
-                            &container_reference.0.user,
-                            &container_reference.0.workdir,
-                            &container_reference.0.volumes,
-                            &container_reference.0.env,
-                        )
-                    });
-            payload.ci.jobs.push(JobDesc {
-                name,
-                script: full_desc.script,
-                image,
-                group: full_desc.group.iter().cloned().collect::<Vec<String>>(),
-                skip_if: full_desc.skip_if,
-            });
+            let full_desc = self.resolve("jobs", &name, &full_desc, &mut vec![])?;
+            if full_desc.script.is_none() {
+                return Err(Error::Invalid(
+                    vec!["jobs".to_string(), name],
+                    "missing field `script`, in the job or the templates it extends".to_string(),
+                ));
+            }
+            let jobs = Self::expand(&name, &full_desc).map_err(|message| {
+                Error::Invalid(vec!["jobs".to_string(), name.clone()], message)
+            })?;
+            if full_desc.matrix.is_some() {
+                payload
+                    .ci
+                    .matrices
+                    .insert(name, jobs.iter().map(|job| job.name.clone()).collect());
+            }
+            payload.ci.jobs.extend(jobs);
         }
 
         if let Some(ref groups) = self.groups {
             payload.ci.groups.clone_from(groups);
         }
 
+        if let Some(ref secrets) = self.secrets {
+            payload.ci.secrets.clone_from(secrets);
+        }
+
+        for (key, set) in [
+            ("display", self.display.is_some()),
+            ("concurrency", self.concurrency.is_some()),
+            ("timeout", self.timeout.is_some()),
+            ("keep_going", self.keep_going.is_some()),
+            ("setup", self.setup.is_some()),
+            ("teardown", self.teardown.is_some()),
+        ] {
+            if set {
+                payload.main_file_keys.push(key);
+            }
+        }
+
+        if self.concurrency.is_some() {
+            payload.ci.concurrency = self.concurrency;
+        }
+
+        if self.timeout.is_some() {
+            payload.ci.timeout = self.timeout;
+        }
+
+        if let Some(keep_going) = self.keep_going {
+            payload.ci.keep_going = keep_going;
+        }
+
+        for (hook, desc, fatal_by_default, script) in [
+            ("setup", &self.setup, true, &mut payload.ci.setup),
+            ("teardown", &self.teardown, false, &mut payload.ci.teardown),
+        ] {
+            if desc.is_some() {
+                *script = HookDesc::script(desc.as_ref(), fatal_by_default, &[])
+                    .map_err(|message| Error::Invalid(vec![hook.to_string()], message))?;
+            }
+        }
+
         if let Some(constraint) = &self.constraints {
             if let Some(blocks) = &constraint.blocks {
                 for (blocker, blocked_jobs) in blocks {
@@ -232,24 +815,7 @@ impl Loader for Version1 {
         }
 
         if let Some(display) = &self.display {
-            if let Some(ok) = &display.ok {
-                payload.display.ok = ok.to_string();
-            }
-            if let Some(ko) = &display.ko {
-                payload.display.ko = ko.to_string();
-            }
-            if let Some(cancelled) = &display.cancelled {
-                payload.display.cancelled = cancelled.to_string();
-            }
-            if let Some(spinner) = &display.spinner {
-                payload.display.spinner = (spinner.frames.clone(), spinner.per_frames);
-            }
-            if let Some(mode) = &display.mode {
-                payload.display.running_display = RunningDisplay::from(*mode);
-            }
-            if let Some(final_display) = &display.final_item {
-                payload.display.final_display = FinalDisplayMode::from(*final_display);
-            }
+            display.apply(&mut payload.display);
         }
 
         if let Some(ref files) = self.extra_files {
@@ -257,5 +823,310 @@ impl Loader for Version1 {
         }
 
         payload.env.clone_from(&self.env);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strvec;
+
+    fn load(text: &str) -> Result<Payload, String> {
+        let mut payload = Payload::default();
+        serde_yaml::from_str::<Version1>(text)
+            .unwrap()
+            .load(&mut payload)
+            .map_err(|error| error.explain("qad.yaml"))?;
+        Ok(payload)
+    }
+
+    #[test]
+    fn matrix() {
+        let payload = load(
+            "version: '1'\n\
+             jobs:\n  \
+               tests:\n    \
+                 image: rust:${{ matrix.toolchain }}\n    \
+                 script: ['cargo test --features ${{matrix.features}}']\n    \
+                 matrix:\n      \
+                   toolchain: [stable, '1.80']\n      \
+                   features: [default, full]\n",
+        )
+        .unwrap();
+
+        let names: Vec<&str> = payload
+            .ci
+            .jobs
+            .iter()
+            .map(|job| job.name.as_str())
+            .collect();
+        assert_eq!(
+            vec![
+                "tests[stable,default]",
+                "tests[stable,full]",
+                "tests[1.80,default]",
+                "tests[1.80,full]"
+            ],
+            names
+        );
+        assert_eq!(
+            strvec!("cargo test --features full"),
+            payload.ci.jobs[3].script
+        );
+        assert_eq!(
+            DockerContainer::new(
+                &"rust:1.80",
+                &"$USER_ID:$GROUP_ID",
+                &"$PWD",
+                &["$PWD:$PWD:rw"],
+                &[] as &[&str]
+            ),
+            payload.ci.jobs[3].image.clone().unwrap()
+        );
+        assert_eq!(payload.ci.matrices["tests"].len(), 4);
+
+        assert_eq!(
+            Err("invalid jobs.fmt in qad.yaml: unknown matrix variable toolchain".to_string()),
+            load(
+                "version: '1'\njobs:\n  fmt:\n    script: ['cargo +${{ matrix.toolchain }} fmt']\n"
+            )
+            .map(|_| ())
+        );
+    }
+
+    #[test]
+    fn templates() {
+        let payload = load(
+            "version: '1'\n\
+             templates:\n  \
+               rust:\n    \
+                 image: rust:latest\n    \
+                 group: check\n    \
+                 skip_if: test -z \"$RUST\"\n  \
+                 slow:\n    \
+                   timeout: 10m\n    \
+                   group: test\n\
+             jobs:\n  \
+               tests:\n    \
+                 extends: [rust, slow]\n    \
+                 script: [cargo test]\n    \
+                 skip_if: 'false'\n",
+        )
+        .unwrap();
+
+        let tests = &payload.ci.jobs[0];
+        assert_eq!(strvec!("cargo test"), tests.script);
+        assert_eq!(strvec!("test"), tests.group);
+        assert_eq!(Some("false".to_string()), tests.skip_if);
+        assert_eq!(Some(Duration::from_secs(600)), tests.timeout);
+        assert!(tests.image.is_some());
+
+        assert_eq!(
+            Err("invalid jobs.fmt.extends in qad.yaml: unknown template rustt".to_string()),
+            load("version: '1'\njobs:\n  fmt:\n    extends: rustt\n    script: [cargo fmt]\n")
+                .map(|_| ())
+        );
+        assert_eq!(
+            Err(
+                "invalid templates.b.extends in qad.yaml: templates extend each other: a -> b -> a"
+                    .to_string()
+            ),
+            load(
+                "version: '1'\n\
+                 templates:\n  a:\n    extends: b\n  b:\n    extends: a\n\
+                 jobs:\n  fmt:\n    extends: a\n    script: [cargo fmt]\n"
+            )
+            .map(|_| ())
+        );
+        assert_eq!(
+            Err("invalid jobs.fmt in qad.yaml: missing field `script`, in the job or the templates it extends".to_string()),
+            load("version: '1'\njobs:\n  fmt:\n    group: check\n").map(|_| ())
+        );
+    }
+
+    #[test]
+    fn variables() {
+        let payload = load(
+            "version: '1'\n\
+             templates:\n  \
+               rust:\n    \
+                 variables:\n      \
+                   RUST_LOG: info\n      \
+                   CARGO_INCREMENTAL: 0\n\
+             jobs:\n  \
+               tests:\n    \
+                 extends: rust\n    \
+                 script: [cargo test]\n    \
+                 env_file: env/${{ matrix.target }}.env\n    \
+                 variables:\n      \
+                   RUST_LOG: debug\n      \
+                   TARGET: ${{ matrix.target }}\n    \
+                 matrix:\n      \
+                   target: [linux]\n",
+        )
+        .unwrap();
+
+        let tests = &payload.ci.jobs[0];
+        assert_eq!(
+            vec![
+                ("RUST_LOG".to_string(), "debug".to_string()),
+                ("CARGO_INCREMENTAL".to_string(), "0".to_string()),
+                ("TARGET".to_string(), "linux".to_string()),
+            ],
+            tests.variables
+        );
+        assert_eq!(Some("env/linux.env".to_string()), tests.env_file);
+    }
+
+    #[test]
+    fn images_and_retries_are_merged_field_by_field() {
+        let payload = load(
+            "version: '1'\n\
+             templates:\n  \
+               rust:\n    \
+                 image:\n      \
+                   image: rust:latest\n      \
+                   volumes: [cache:/cache]\n      \
+                   lifetime: job\n    \
+                 retry: { max: 2 }\n\
+             jobs:\n  \
+               tests:\n    \
+                 extends: rust\n    \
+                 script: [cargo test]\n    \
+                 image: { user: root }\n    \
+                 retry: { when: [timed_out] }\n",
+        )
+        .unwrap();
+
+        let tests = &payload.ci.jobs[0];
+        assert_eq!(
+            Some(
+                DockerContainer::new(
+                    &"rust:latest",
+                    &"root",
+                    &"$PWD",
+                    &["cache:/cache"],
+                    &[""; 0]
+                )
+                .with_lifetime(Lifetime::Job)
+            ),
+            tests.image
+        );
+        assert_eq!(
+            Retry {
+                max: 2,
+                when: vec![Failure::TimedOut]
+            },
+            tests.retry
+        );
+
+        assert_eq!(
+            Err("invalid jobs.tests in qad.yaml: missing field `retry.max`, in the job or the templates it extends".to_string()),
+            load("version: '1'\njobs:\n  tests:\n    script: [cargo test]\n    retry: { when: [timed_out] }\n")
+                .map(|_| ())
+        );
+    }
+
+    #[test]
+    fn retry() {
+        let payload = load(
+            "version: '1'\n\
+             jobs:\n  \
+               tests:\n    \
+                 script: [cargo test]\n    \
+                 retry:\n      \
+                   max: 2\n      \
+                   when: [job_error, timed_out]\n  \
+               deploy:\n    \
+                 script: [make deploy]\n    \
+                 retry: { max: 1 }\n",
+        )
+        .unwrap();
+
+        let retry = |name: &str| {
+            payload
+                .ci
+                .jobs
+                .iter()
+                .find(|job| job.name == name)
+                .unwrap()
+                .retry
+                .clone()
+        };
+        assert_eq!(
+            Retry {
+                max: 2,
+                when: vec![Failure::JobError, Failure::TimedOut]
+            },
+            retry("tests")
+        );
+        assert_eq!(1, retry("deploy").max);
+        assert_eq!(3, retry("deploy").when.len());
+    }
+
+    #[test]
+    fn hooks() {
+        let payload = load(
+            "version: '1'\n\
+             setup: [docker compose up -d]\n\
+             teardown:\n  \
+               script: [docker compose down]\n  \
+               fatal: true\n\
+             jobs:\n  \
+               tests:\n    \
+                 script: [cargo test]\n    \
+                 before_script: ['mkdir -p ${{ matrix.dir }}']\n    \
+                 after_script:\n      \
+                   script: ['rm -rf ${{ matrix.dir }}']\n    \
+                 matrix:\n      \
+                   dir: [tmp]\n",
+        )
+        .unwrap();
+
+        let script = |instruction: &str, fatal: bool| HookScript {
+            instructions: vec![instruction.to_string()],
+            fatal,
+        };
+        assert_eq!(script("docker compose up -d", true), payload.ci.setup);
+        assert_eq!(script("docker compose down", true), payload.ci.teardown);
+        let tests = &payload.ci.jobs[0];
+        assert_eq!(script("mkdir -p tmp", true), tests.before_script);
+        assert_eq!(script("rm -rf tmp", false), tests.after_script);
+    }
+
+    #[test]
+    fn container_lifetime() {
+        let payload = load(
+            "version: '1'\n\
+             jobs:\n  \
+               tests:\n    \
+                 script: [apk add make, make test]\n    \
+                 image:\n      \
+                   image: alpine\n      \
+                   env: []\n      \
+                   volumes: []\n      \
+                   user: root\n      \
+                   workdir: /src\n      \
+                   lifetime: job\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            DockerContainer::new(&"alpine", &"root", &"/src", &[] as &[&str], &[] as &[&str])
+                .with_lifetime(Lifetime::Job),
+            payload.ci.jobs[0].image.clone().unwrap()
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(Ok(Duration::from_secs(90)), parse_duration("90"));
+        assert_eq!(Ok(Duration::from_secs(90)), parse_duration("90s"));
+        assert_eq!(Ok(Duration::from_secs(300)), parse_duration("5m"));
+        assert_eq!(Ok(Duration::from_secs(5400)), parse_duration("1h30m"));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("ten minutes").is_err());
+        assert!(parse_duration("5124095576030432h").is_err());
     }
 }
@@ -15,7 +15,7 @@ use std::sync::mpsc::channel;
 use crate::app::domain::{Event, State};
 use crate::ci::config::CliOption;
 use crate::ci::Ci;
-use crate::config::argh::{Args, CiArgs, Subcommands};
+use crate::config::argh::{Args, CiArgs, RerunArgs, Subcommands, WatchArgs};
 use crate::config::Config;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -90,12 +90,14 @@ fn main() {
                 eprintln!("# To register {PACKAGE_NAME}'s bash autocompletion script");
                 eprintln!("# {file}:");
                 eprintln!("# mkdir -p {DIR}");
-                eprintln!("# {PACKAGE_NAME} autocomplete > {file}",);
+                eprintln!("# {PACKAGE_NAME} autocomplete > {file}");
             }
             std::process::exit(0);
         }
         Subcommands::App(_) => app(),
         Subcommands::Ci(arg) => ci_run(&config, arg, no_tty),
+        Subcommands::Rerun(arg) => rerun(&config, &arg, no_tty),
+        Subcommands::Watch(arg) => watch(&config, arg, no_tty),
         Subcommands::Debug(arg) => match Ci::debug(&config, arg.nested) {
             Ok(true) => {}
             Ok(false) => {
@@ -106,6 +108,14 @@ fn main() {
                 std::process::exit(2)
             }
         },
+        Subcommands::Graph(arg) => match Ci::graph(&config, arg.format) {
+            Ok(()) => {}
+            Err(str) => {
+                eprintln!("{PACKAGE_NAME}: {str}");
+                std::process::exit(2)
+            }
+        },
+        Subcommands::Check(_) => check(&config),
         Subcommands::List(_) => match Ci::list(&config) {
             Ok(()) => {}
             Err(str) => {
@@ -122,13 +132,70 @@ fn main() {
 }
 
 fn ci_run(config: &Config, arg: CiArgs, no_tty: bool) {
-    match Ci::run(
+    exit_with(Ci::run(
+        config,
+        &CliOption {
+            only: arg.nested,
+            with_deps: arg.with_deps,
+            skip: arg.skip,
+            no_cache: arg.no_cache,
+            keep_going: arg.keep_going,
+            strict: arg.strict,
+            no_tty,
+            jobs: arg.jobs,
+            format: arg.format,
+        },
+    ));
+}
+
+fn rerun(config: &Config, arg: &RerunArgs, no_tty: bool) {
+    exit_with(Ci::rerun(
+        config,
+        &CliOption {
+            no_cache: arg.no_cache,
+            keep_going: arg.keep_going,
+            strict: arg.strict,
+            no_tty,
+            jobs: arg.jobs,
+            format: arg.format,
+            ..Default::default()
+        },
+        arg.failed,
+    ));
+}
+
+fn watch(config: &Config, arg: WatchArgs, no_tty: bool) {
+    exit_with(Ci::watch(
         config,
         &CliOption {
-            job: arg.nested,
+            only: arg.nested,
+            strict: arg.strict,
             no_tty,
+            jobs: arg.jobs,
+            ..Default::default()
         },
-    ) {
+    ));
+}
+
+fn check(config: &Config) {
+    match config::check::check(config) {
+        Ok(problems) if problems.is_empty() => {}
+        Ok(problems) => {
+            for problem in &problems {
+                eprintln!("{problem}");
+            }
+            eprintln!("{PACKAGE_NAME}: {} problem(s) found", problems.len());
+            std::process::exit(1);
+        }
+        Err(str) => {
+            eprintln!("{PACKAGE_NAME}: {str}");
+            std::process::exit(2)
+        }
+    }
+}
+
+fn exit_with(result: anyhow::Result<bool>) {
+    match result {
         Ok(true) => {}
         Ok(false) => {
             std::process::exit(1);
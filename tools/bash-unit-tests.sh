@@ -17,10 +17,16 @@ unit() {
   fi
 }
 
-unit "qad " "app" "autocomplete" "ci" "debug" "list" "has-ci" "--version" "--help" "--file" "--no-tty"
+unit "qad " "app" "autocomplete" "ci" "debug" "list" "has-ci" "rerun" "watch" "graph" "check" "--version" "--help" "--file" "--no-tty"
 unit "qad az" ""
 unit "qad a" "app"
 unit "qad auto" "autocomplete"
-unit "qad c" "ci"
+unit "qad c" "ci" "check"
+unit "qad ci -j" "-j"
+unit "qad ci --j" "--jobs"
+unit "qad ci --f" "--format"
+unit "qad ci --format " "human" "ndjson"
+unit "qad rerun --format n" "ndjson"
+unit "qad graph --format " "text" "dot" "mermaid"
 
 exit "$err_counter"